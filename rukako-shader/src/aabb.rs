@@ -12,6 +12,8 @@ pub struct AABB {
 }
 
 impl AABB {
+    /// Slab test. Entering and leaving at the same `t` still counts as a hit: far from the
+    /// origin the padding of a flat box can be below the precision of `t`
     pub fn hit(&self, ray: &Ray, mut t_min: f32, mut t_max: f32) -> Bool32 {
         {
             let inv_d = 1.0 / ray.direction.x;
@@ -25,7 +27,7 @@ impl AABB {
             t_min = t0.max(t_min);
             t_max = t1.min(t_max);
 
-            if t_max <= t_min {
+            if t_max < t_min {
                 return Bool32::FALSE;
             }
         }
@@ -41,7 +43,7 @@ impl AABB {
             t_min = t0.max(t_min);
             t_max = t1.min(t_max);
 
-            if t_max <= t_min {
+            if t_max < t_min {
                 return Bool32::FALSE;
             }
         }
@@ -57,13 +59,33 @@ impl AABB {
             t_min = t0.max(t_min);
             t_max = t1.min(t_max);
 
-            if t_max <= t_min {
+            if t_max < t_min {
                 return Bool32::FALSE;
             }
         }
 
         Bool32::TRUE
     }
+
+    /// Widens every axis thinner than `delta` to `delta`, so that flat primitives do not get
+    /// a box of zero thickness, which rays could slip through
+    pub fn pad(&self, delta: f32) -> Self {
+        let mut minimum = self.minimum;
+        let mut maximum = self.maximum;
+        if maximum.x - minimum.x < delta {
+            minimum.x -= 0.5 * delta;
+            maximum.x += 0.5 * delta;
+        }
+        if maximum.y - minimum.y < delta {
+            minimum.y -= 0.5 * delta;
+            maximum.y += 0.5 * delta;
+        }
+        if maximum.z - minimum.z < delta {
+            minimum.z -= 0.5 * delta;
+            maximum.z += 0.5 * delta;
+        }
+        Self { minimum, maximum }
+    }
 }
 
 pub fn surrounding_box(box0: AABB, box1: AABB) -> AABB {
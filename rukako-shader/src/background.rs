@@ -0,0 +1,90 @@
+use spirv_std::glam::{Vec3, Vec4, Vec4Swizzles};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+
+use crate::{math::sphere_uv, ray::Ray};
+
+/// What a ray that hits nothing sees
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct EnumBackground {
+    v0: Vec4,
+    v1: Vec4,
+    t: u32,
+    width: u32,
+    height: u32,
+}
+
+struct Gradient<'a> {
+    data: &'a EnumBackground,
+}
+
+struct Solid<'a> {
+    data: &'a EnumBackground,
+}
+
+struct EnvironmentMap<'a> {
+    data: &'a EnumBackground,
+}
+
+impl<'a> Gradient<'a> {
+    fn bottom(&self) -> Vec3 {
+        self.data.v0.xyz()
+    }
+
+    fn top(&self) -> Vec3 {
+        self.data.v1.xyz()
+    }
+
+    fn color(&self, ray: &Ray) -> Vec3 {
+        let unit_direction = ray.direction.normalize();
+        let t = 0.5 * (unit_direction.y + 1.0);
+        self.bottom().lerp(self.top(), t)
+    }
+}
+
+impl<'a> Solid<'a> {
+    fn color(&self) -> Vec3 {
+        self.data.v0.xyz()
+    }
+}
+
+impl<'a> EnvironmentMap<'a> {
+    fn intensity(&self) -> f32 {
+        self.data.v0.x
+    }
+
+    /// Equirectangular lookup. The first row of `texels` is the top of the image.
+    fn color(&self, ray: &Ray, texels: &[Vec4]) -> Vec3 {
+        let (u, v) = sphere_uv(ray.direction.normalize());
+
+        let width = self.data.width;
+        let height = self.data.height;
+
+        let i = ((u * width as f32) as u32).min(width - 1);
+        let j = (((1.0 - v) * height as f32) as u32).min(height - 1);
+
+        self.intensity() * texels[(j * width + i) as usize].xyz()
+    }
+}
+
+impl EnumBackground {
+    pub fn new(v0: Vec4, v1: Vec4, t: u32, width: u32, height: u32) -> Self {
+        Self {
+            v0,
+            v1,
+            t,
+            width,
+            height,
+        }
+    }
+
+    /// `texels` starts with those of the environment map, if any
+    pub fn color(&self, ray: &Ray, texels: &[Vec4]) -> Vec3 {
+        match self.t {
+            0 => Gradient { data: self }.color(ray),
+            1 => Solid { data: self }.color(),
+            _ => EnvironmentMap { data: self }.color(ray, texels),
+        }
+    }
+}
@@ -3,15 +3,16 @@ use spirv_std::glam::UVec4;
 use crate::{
     aabb::AABB,
     bool::Bool32,
-    hittable::{HitRecord, Hittable},
+    hittable::{HitRecord, NO_PRIMITIVE},
+    rand::DefaultRng,
     ray::Ray,
-    sphere::Sphere,
+    world::World,
 };
 
 #[repr(C)]
 pub struct BVHNode {
-    aabb: AABB,
-    child: UVec4,
+    pub(crate) aabb: AABB,
+    pub(crate) child: UVec4,
 }
 
 #[repr(C)]
@@ -19,16 +20,25 @@ pub struct BVH<'a> {
     pub nodes: &'a [BVHNode],
 }
 
+/// Capacity of the traversal stack. `pod::bvh::create_bvh` refuses trees that need more.
+pub const STACK_SIZE: usize = 32;
+
 #[derive(Default)]
 struct Stack {
     len: u32,
-    data: [u32; 32],
+    data: [u32; STACK_SIZE],
 }
 
 impl Stack {
     fn push(&mut self, element: u32) {
-        self.data[self.len as usize] = element;
-        self.len += 1;
+        // `build_tree` rejects trees deeper than `STACK_SIZE` by `max_stack_depth`, so
+        // dropping is unreachable for them. The check only keeps other trees in bounds
+        #[cfg(not(target_arch = "spirv"))]
+        debug_assert!(self.len < STACK_SIZE as u32, "BVH stack overflow");
+        if self.len < STACK_SIZE as u32 {
+            self.data[self.len as usize] = element;
+            self.len += 1;
+        }
     }
 
     fn pop(&mut self) -> u32 {
@@ -42,38 +52,59 @@ impl Stack {
 }
 
 impl<'a> BVH<'a> {
+    /// Traverses the top-level BVH rooted at the first node. Its leaves hold primitives in
+    /// world space or instances, whose BVHs are traversed in object space.
     pub fn hit(
         &self,
         ray: &Ray,
         t_min: f32,
         mut t_max: f32,
         hit_record: &mut HitRecord,
-        world: &[Sphere],
+        world: &World,
+        rng: &mut DefaultRng,
     ) -> Bool32 {
         let mut stack = Stack::default();
         let mut hit = Bool32::FALSE;
         stack.push(0);
 
         while (!stack.is_empty()).into() {
-            let i = stack.pop();
+            let node = &self.nodes[stack.pop() as usize];
 
-            if (!self.nodes[i as usize].aabb.hit(ray, t_min, t_max)).into() {
+            if (!self.visit(node, ray, t_min, t_max, &mut stack)).into() {
                 continue;
             }
 
-            match self.nodes[i as usize].child.x {
-                0 => {
-                    stack.push(self.nodes[i as usize].child.y);
+            if node.child.x == 2 {
+                if self
+                    .hit_primitives(node, ray, t_min, t_max, hit_record, world, rng)
+                    .into()
+                {
+                    t_max = hit_record.t;
+                    hit = Bool32::TRUE;
                 }
-                1 => {
-                    stack.push(self.nodes[i as usize].child.y);
-                    stack.push(self.nodes[i as usize].child.z);
-                }
-                _ => {
-                    if world[self.nodes[i as usize].child.w as usize]
-                        .hit(ray, t_min, t_max, hit_record)
+            } else {
+                // An instance leaf holds `child.y` instances starting at `child.w`
+                let first = node.child.w;
+                let count = node.child.y;
+                for k in first..first + count {
+                    let instance = &world.instances[k as usize];
+                    let object_ray = instance.to_object(ray);
+
+                    if self
+                        .hit_object(
+                            instance.root(),
+                            &object_ray,
+                            t_min,
+                            t_max,
+                            hit_record,
+                            world,
+                            rng,
+                        )
                         .into()
                     {
+                        instance.to_world(ray, hit_record);
+                        // The primitives of objects are never in the light list
+                        hit_record.primitive = NO_PRIMITIVE;
                         t_max = hit_record.t;
                         hit = Bool32::TRUE;
                     }
@@ -83,4 +114,105 @@ impl<'a> BVH<'a> {
 
         hit
     }
+
+    /// Traverses the BVH of an instanced object, whose leaves only hold primitives
+    #[allow(clippy::too_many_arguments)]
+    fn hit_object(
+        &self,
+        root: u32,
+        ray: &Ray,
+        t_min: f32,
+        mut t_max: f32,
+        hit_record: &mut HitRecord,
+        world: &World,
+        rng: &mut DefaultRng,
+    ) -> Bool32 {
+        let mut stack = Stack::default();
+        let mut hit = Bool32::FALSE;
+        stack.push(root);
+
+        while (!stack.is_empty()).into() {
+            let node = &self.nodes[stack.pop() as usize];
+
+            if (!self.visit(node, ray, t_min, t_max, &mut stack)).into() {
+                continue;
+            }
+
+            if self
+                .hit_primitives(node, ray, t_min, t_max, hit_record, world, rng)
+                .into()
+            {
+                t_max = hit_record.t;
+                hit = Bool32::TRUE;
+            }
+        }
+
+        hit
+    }
+
+    /// Pushes the children of an inner node hit by the ray. Returns whether `node` is a leaf
+    /// hit by the ray.
+    fn visit(
+        &self,
+        node: &BVHNode,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        stack: &mut Stack,
+    ) -> Bool32 {
+        if (!node.aabb.hit(ray, t_min, t_max)).into() {
+            return Bool32::FALSE;
+        }
+
+        match node.child.x {
+            0 => {
+                stack.push(node.child.y);
+                Bool32::FALSE
+            }
+            1 => {
+                // `create_bvh` puts the smaller subtree in `z` so that it is visited
+                // first, which keeps the stack within log2 of the primitive count
+                stack.push(node.child.y);
+                stack.push(node.child.z);
+                Bool32::FALSE
+            }
+            // Primitive leaves (2) and instance leaves (3)
+            _ => Bool32::TRUE,
+        }
+    }
+
+    /// A primitive leaf holds `child.y` primitives starting at `child.w`
+    #[allow(clippy::too_many_arguments)]
+    fn hit_primitives(
+        &self,
+        node: &BVHNode,
+        ray: &Ray,
+        t_min: f32,
+        mut t_max: f32,
+        hit_record: &mut HitRecord,
+        world: &World,
+        rng: &mut DefaultRng,
+    ) -> Bool32 {
+        let mut hit = Bool32::FALSE;
+        let first = node.child.w;
+        let count = node.child.y;
+        for k in first..first + count {
+            let hit_primitive = world.primitives[k as usize].hit(
+                ray,
+                t_min,
+                t_max,
+                hit_record,
+                world.vertices,
+                rng,
+            );
+
+            if hit_primitive.into() {
+                hit_record.primitive = k;
+                t_max = hit_record.t;
+                hit = Bool32::TRUE;
+            }
+        }
+
+        hit
+    }
 }
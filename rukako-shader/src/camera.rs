@@ -8,17 +8,18 @@ use crate::rand::DefaultRng;
 use crate::ray::Ray;
 
 #[derive(Copy, Clone)]
+#[repr(C)]
 pub struct Camera {
-    origin: Vec3,
-    lower_left_corner: Vec3,
-    horizontal: Vec3,
-    vertical: Vec3,
-    u: Vec3,
-    v: Vec3,
+    pub(crate) origin: Vec3,
+    pub(crate) lower_left_corner: Vec3,
+    pub(crate) horizontal: Vec3,
+    pub(crate) vertical: Vec3,
+    pub(crate) u: Vec3,
+    pub(crate) v: Vec3,
     // w: Vec3,
-    lens_radius: f32,
-    time0: f32,
-    time1: f32,
+    pub(crate) lens_radius: f32,
+    pub(crate) time0: f32,
+    pub(crate) time1: f32,
 }
 
 impl Camera {
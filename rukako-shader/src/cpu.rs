@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::prelude::*;
+use rayon::prelude::*;
+use spirv_std::glam::Vec4;
+
+use crate::{
+    background::EnumBackground, bvh::BVHNode, camera::Camera, sample_pixel, world::World,
+    ShaderConstants,
+};
+
+/// Side of the square tiles `render` hands to rayon
+pub const TILE_SIZE: usize = 16;
+
+/// Number of tiles `render` splits an image into
+pub fn tile_count(width: usize, height: usize) -> usize {
+    ((width + TILE_SIZE - 1) / TILE_SIZE) * ((height + TILE_SIZE - 1) / TILE_SIZE)
+}
+
+/// Renders with the shader code running natively, one rayon task per tile taking all the
+/// samples of its pixels. The seed of every sample is drawn from `rng` up front in the same
+/// order as the GPU backend, so both produce the same image. `progress` is called with the
+/// number of tiles done out of `tile_count(width, height)`, from any thread.
+///
+/// Returns the accumulation buffer `main_cs` writes, top row first.
+#[allow(clippy::too_many_arguments)]
+pub fn render(
+    width: usize,
+    height: usize,
+    n_samples: usize,
+    world: &World,
+    bvh: &[BVHNode],
+    camera: &Camera,
+    background: &EnumBackground,
+    rng: &mut impl Rng,
+    progress: impl Fn(usize) + Sync,
+) -> Vec<Vec4> {
+    // The GPU backend draws a seed for its constants before the first sample
+    let _: u32 = rng.gen();
+    let seeds: Vec<u32> = (0..n_samples).map(|_| rng.gen()).collect();
+
+    let tiles: Vec<(usize, usize)> = (0..height)
+        .step_by(TILE_SIZE)
+        .flat_map(|y0| (0..width).step_by(TILE_SIZE).map(move |x0| (x0, y0)))
+        .collect();
+    let done = AtomicUsize::new(0);
+
+    let tile_pixels: Vec<Vec<Vec4>> = tiles
+        .par_iter()
+        .map(|&(x0, y0)| {
+            let (x1, y1) = ((x0 + TILE_SIZE).min(width), (y0 + TILE_SIZE).min(height));
+            let mut pixels = vec![Vec4::ZERO; (x1 - x0) * (y1 - y0)];
+            for &seed in &seeds {
+                let constants = ShaderConstants {
+                    width: width as u32,
+                    height: height as u32,
+                    seed,
+                    n_lights: world.n_lights,
+                };
+                let rows = (y0..y1).flat_map(|row| (x0..x1).map(move |x| (x, row)));
+                for ((x, row), pixel) in rows.zip(pixels.iter_mut()) {
+                    let y = (height - row - 1) as u32;
+                    *pixel += sample_pixel(x as u32, y, &constants, world, bvh, camera, background)
+                        .extend(1.0);
+                }
+            }
+            progress(done.fetch_add(1, Ordering::Relaxed) + 1);
+            pixels
+        })
+        .collect();
+
+    let mut out = vec![Vec4::ZERO; width * height];
+    for (&(x0, y0), pixels) in tiles.iter().zip(tile_pixels) {
+        let tile_width = (x0 + TILE_SIZE).min(width) - x0;
+        for (row, tile_row) in pixels.chunks_exact(tile_width).enumerate() {
+            let start = (y0 + row) * width + x0;
+            out[start..start + tile_width].copy_from_slice(tile_row);
+        }
+    }
+
+    out
+}
@@ -1,14 +1,25 @@
-use spirv_std::glam::Vec3;
+use spirv_std::glam::{Vec2, Vec3};
 
 use crate::{aabb::AABB, bool::Bool32, material::EnumMaterial, ray::Ray};
 
+/// `HitRecord::primitive` of hits within an instance
+pub const NO_PRIMITIVE: u32 = u32::MAX;
+
 #[derive(Clone, Default)]
 pub struct HitRecord {
     pub position: Vec3,
+    /// Position in the space of the object hit, where procedural textures are evaluated so
+    /// that they follow instances. The same as `position` outside of instances.
+    pub local_position: Vec3,
     pub normal: Vec3,
     pub material: EnumMaterial,
     pub t: f32,
     pub front_face: Bool32,
+    /// Texture coordinates of the hit. Longitude and latitude for spheres, position along
+    /// the sides for quads and rectangles, barycentric for triangles.
+    pub uv: Vec2,
+    /// Index of the primitive hit in the world buffer, set by `bvh::BVH::hit`
+    pub primitive: u32,
 }
 
 impl HitRecord {
@@ -28,10 +39,13 @@ impl HitRecord {
 
         Self {
             position,
+            local_position: position,
             normal,
             t,
             front_face: front_face.into(),
             material,
+            uv: Vec2::ZERO,
+            primitive: 0,
         }
     }
 }
@@ -0,0 +1,74 @@
+use spirv_std::glam::{vec3, UVec4, Vec3, Vec4, Vec4Swizzles};
+
+use crate::{bool::Bool32, hittable::HitRecord, material::EnumMaterial, ray::Ray};
+
+/// An object placed in the world by an affine transform. The object is a range of the
+/// primitive buffer with a BVH of its own, shared by all of its instances.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct Instance {
+    /// Rows of the 3x4 matrix taking world space to object space
+    row0: Vec4,
+    row1: Vec4,
+    row2: Vec4,
+    /// `x`: index of the object. `y`: 1 if `material` replaces the materials of the
+    /// object. `z`: first node of the BVH of the object
+    u0: UVec4,
+    material: EnumMaterial,
+}
+
+impl Instance {
+    pub fn new(rows: [Vec4; 3], u0: UVec4, material: EnumMaterial) -> Self {
+        Self {
+            row0: rows[0],
+            row1: rows[1],
+            row2: rows[2],
+            u0,
+            material,
+        }
+    }
+
+    pub fn root(&self) -> u32 {
+        self.u0.z
+    }
+
+    /// The ray in object space. Its direction is not normalized, so distances along it are the
+    /// same as in world space.
+    pub fn to_object(&self, ray: &Ray) -> Ray {
+        let origin = ray.origin.extend(1.0);
+        Ray {
+            origin: vec3(
+                self.row0.dot(origin),
+                self.row1.dot(origin),
+                self.row2.dot(origin),
+            ),
+            direction: vec3(
+                self.row0.xyz().dot(ray.direction),
+                self.row1.xyz().dot(ray.direction),
+                self.row2.xyz().dot(ray.direction),
+            ),
+            time: ray.time,
+        }
+    }
+
+    /// Normals go back to world space by the transpose of the world to object matrix
+    fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        (normal.x * self.row0.xyz() + normal.y * self.row1.xyz() + normal.z * self.row2.xyz())
+            .normalize()
+    }
+
+    /// Moves a hit of `to_object(ray)` back to world space and applies the material override.
+    /// `t` and `front_face` are unchanged by the transform, and `local_position` stays in
+    /// object space.
+    pub fn to_world(&self, ray: &Ray, hit_record: &mut HitRecord) {
+        hit_record.position = ray.at(hit_record.t);
+        hit_record.normal = self.normal_to_world(hit_record.normal);
+        if self.overrides_material().into() {
+            hit_record.material = self.material;
+        }
+    }
+
+    fn overrides_material(&self) -> Bool32 {
+        Bool32::new(self.u0.y != 0)
+    }
+}
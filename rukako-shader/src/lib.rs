@@ -5,31 +5,48 @@
     register_attr(spirv)
 )]
 
-use crate::rand::DefaultRng;
+use crate::{bool::Bool32, rand::DefaultRng};
+use background::EnumBackground;
 use camera::Camera;
-use hittable::HitRecord;
+use hittable::{HitRecord, NO_PRIMITIVE};
+use instance::Instance;
 use material::{Material, Scatter};
+use primitive::EnumPrimitive;
 use ray::Ray;
 use spirv_std::glam::{vec3, UVec3, Vec3, Vec4};
 #[cfg(not(target_arch = "spirv"))]
 use spirv_std::macros::spirv;
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
-use spirv_std::num_traits::FloatConst;
+use texture::{EnumTexture, Textures};
+use triangle::Vertex;
+use world::World;
 
 use bytemuck::{Pod, Zeroable};
 
 pub mod aabb;
+pub mod background;
 pub mod bool;
 pub mod bvh;
 pub mod camera;
+#[cfg(not(target_arch = "spirv"))]
+pub mod cpu;
 pub mod hittable;
+pub mod instance;
+pub mod light;
 pub mod material;
 pub mod math;
+pub mod medium;
+pub mod onb;
 pub mod pod;
+pub mod primitive;
+pub mod quad;
 pub mod rand;
 pub mod ray;
 pub mod sphere;
+pub mod texture;
+pub mod triangle;
+pub mod world;
 
 #[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
@@ -37,6 +54,8 @@ pub struct ShaderConstants {
     pub width: u32,
     pub height: u32,
     pub seed: u32,
+    /// Number of entries of the light buffer in use
+    pub n_lights: u32,
 }
 
 /*
@@ -62,36 +81,118 @@ fn hit(
 }
 */
 
+/// Samples one light with a shadow ray from a hit on a material without delta lobes.
+/// Weighted by the balance heuristic against the sampling of the material the path continues
+/// with.
+fn sample_light(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    world: &World,
+    bvh: &[bvh::BVHNode],
+    rng: &mut DefaultRng,
+) -> Vec3 {
+    let n_lights = world.n_lights as f32;
+    let index = ((rng.next_f32() * n_lights) as u32).min(world.n_lights - 1);
+    let primitive = world.lights[index as usize];
+    let light = &world.primitives[primitive as usize];
+
+    let shadow_ray = Ray {
+        origin: hit_record.position,
+        direction: light.random(hit_record.position, ray.time, world.vertices, rng),
+        time: ray.time,
+    };
+    let light_pdf = light.pdf_value(&shadow_ray, world.vertices) / n_lights;
+    let material = hit_record.material;
+    let f = material.eval(ray, hit_record, &world.textures, shadow_ray.direction);
+    if Bool32::new(light_pdf <= 0.0)
+        .or(Bool32::new(f.max_element() <= 0.0))
+        .into()
+    {
+        return Vec3::ZERO;
+    }
+
+    let mut shadow = HitRecord::default();
+    if (!(bvh::BVH { nodes: bvh }).hit(&shadow_ray, 0.001, f32::INFINITY, &mut shadow, world, rng))
+        .into()
+    {
+        return Vec3::ZERO;
+    }
+    // Something else is in the way
+    if shadow.primitive != primitive {
+        return Vec3::ZERO;
+    }
+
+    let bsdf_pdf = material.pdf(ray, hit_record, shadow_ray.direction);
+    let weight = light_pdf / (light_pdf + bsdf_pdf);
+    weight * f * shadow.material.emitted(&shadow) / light_pdf
+}
+
+/// Bounces after which paths are terminated by Russian roulette
+const ROULETTE_DEPTH: u32 = 3;
+
+/// Paths are terminated by Russian roulette after `roulette_depth` bounces
 fn ray_color(
     mut ray: Ray,
-    world: &[sphere::Sphere],
+    world: &World,
     bvh: &[bvh::BVHNode],
+    background: &EnumBackground,
+    roulette_depth: u32,
     rng: &mut DefaultRng,
 ) -> Vec3 {
-    let mut color = vec3(1.0, 1.0, 1.0);
+    let mut color = vec3(0.0, 0.0, 0.0);
+    let mut attenuation = vec3(1.0, 1.0, 1.0);
     let mut hit_record = HitRecord::default();
     let mut scatter = Scatter::default();
+    // Density of the material sampling that chose the direction of `ray` after a light was
+    // sampled from its origin, or else 0
+    let mut bsdf_pdf = 0.0;
 
-    for _ in 0..50 {
+    for depth in 0..50 {
         if (bvh::BVH { nodes: bvh })
-            .hit(&ray, 0.001, f32::INFINITY, &mut hit_record, world)
+            .hit(&ray, 0.001, f32::INFINITY, &mut hit_record, world, rng)
             .into()
         {
             let material = hit_record.material;
 
-            if material
-                .scatter(&ray, &hit_record, rng, &mut scatter)
+            let emitted = material.emitted(&hit_record);
+            // Lights could also have been reached by `sample_light`
+            let weight = if Bool32::new(bsdf_pdf > 0.0)
+                .and(Bool32::new(hit_record.primitive != NO_PRIMITIVE))
+                .and(Bool32::new(emitted.max_element() > 0.0))
                 .into()
             {
-                color *= scatter.color;
-                ray = scatter.ray;
+                let light = &world.primitives[hit_record.primitive as usize];
+                let light_pdf = light.pdf_value(&ray, world.vertices) / world.n_lights as f32;
+                bsdf_pdf / (bsdf_pdf + light_pdf)
             } else {
+                1.0
+            };
+            color += weight * attenuation * emitted;
+
+            if (!material.sample(&ray, &hit_record, &world.textures, rng, &mut scatter)).into() {
                 break;
             }
+
+            if Bool32::new(world.n_lights > 0).and(!scatter.delta).into() {
+                color += attenuation * sample_light(&ray, &hit_record, world, bvh, rng);
+                bsdf_pdf = scatter.pdf;
+            } else {
+                bsdf_pdf = 0.0;
+            }
+
+            attenuation *= scatter.color;
+            ray = scatter.ray;
+
+            // Terminate dim paths early, compensating the ones that survive
+            if depth >= roulette_depth {
+                let survival = attenuation.max_element().min(0.95);
+                if rng.next_f32() >= survival {
+                    break;
+                }
+                attenuation /= survival;
+            }
         } else {
-            let unit_direction = ray.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.0);
-            color *= vec3(1.0, 1.0, 1.0).lerp(vec3(0.5, 0.7, 1.0), t);
+            color += attenuation * background.color(&ray, world.textures.texels);
             break;
         };
     }
@@ -99,16 +200,43 @@ fn ray_color(
     color
 }
 
+pub fn sample_pixel(
+    x: u32,
+    y: u32,
+    constants: &ShaderConstants,
+    world: &World,
+    bvh: &[bvh::BVHNode],
+    camera: &Camera,
+    background: &EnumBackground,
+) -> Vec3 {
+    let seed = constants.seed ^ (constants.width * y + x);
+    let mut rng = DefaultRng::new(seed);
+
+    let u = (x as f32 + rng.next_f32()) / (constants.width - 1) as f32;
+    let v = (y as f32 + rng.next_f32()) / (constants.height - 1) as f32;
+
+    let ray = camera.get_ray(u, v, &mut rng);
+    ray_color(ray, world, bvh, background, ROULETTE_DEPTH, &mut rng)
+}
+
 pub const NUM_THREADS_X: u32 = 8;
 pub const NUM_THREADS_Y: u32 = 8;
 
+#[allow(clippy::too_many_arguments)]
 #[spirv(compute(threads(/* NUM_THREADS_X */ 8, /* NUM_THREADS_Y */ 8, 1)))]
 pub fn main_cs(
     #[spirv(global_invocation_id)] id: UVec3,
     #[spirv(push_constant)] constants: &ShaderConstants,
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] world: &[sphere::Sphere],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] world: &[EnumPrimitive],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] bvh: &[bvh::BVHNode],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] out: &mut [Vec4],
+    #[spirv(uniform, descriptor_set = 0, binding = 3)] camera: &Camera,
+    #[spirv(uniform, descriptor_set = 0, binding = 4)] background: &EnumBackground,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] texels: &[Vec4],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 6)] vertices: &[Vertex],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 7)] instances: &[Instance],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 8)] textures: &[EnumTexture],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 9)] lights: &[u32],
 ) {
     let x = id.x;
     let y = id.y;
@@ -121,26 +249,91 @@ pub fn main_cs(
         return;
     }
 
-    let seed = constants.seed ^ (constants.width * y + x);
-    let mut rng = DefaultRng::new(seed);
+    let world = World {
+        primitives: world,
+        vertices,
+        instances,
+        textures: Textures {
+            records: textures,
+            texels,
+        },
+        lights,
+        n_lights: constants.n_lights,
+    };
+    let color = sample_pixel(x, y, constants, &world, bvh, camera, background);
+
+    out[((constants.height - y - 1) * constants.width + x) as usize] += color.extend(1.0);
+}
 
-    let camera = Camera::new(
-        vec3(13.0, 2.0, 3.0),
-        vec3(0.0, 0.0, 0.0),
-        vec3(0.0, 1.0, 0.0),
-        20.0 / 180.0 * f32::PI(),
-        constants.width as f32 / constants.height as f32,
-        0.1,
-        10.0,
-        0.0,
-        1.0,
-    );
+#[cfg(test)]
+mod tests {
+    use ::rand::{rngs::StdRng, SeedableRng};
 
-    let u = (x as f32 + rng.next_f32()) / (constants.width - 1) as f32;
-    let v = (y as f32 + rng.next_f32()) / (constants.height - 1) as f32;
+    use super::*;
+    use crate::pod::{
+        bvh::{create_bvh, BVHBuilder},
+        BackgroundPod, EnumMaterialPod, EnumPrimitivePod,
+    };
 
-    let ray = camera.get_ray(u, v, &mut rng);
-    let color = ray_color(ray, world, bvh, &mut rng);
+    /// Mean radiance reaching rays shot at a cluster of white lambertian spheres under a
+    /// uniform white sky. Nothing is absorbed, so it is 1 whenever the paths are unbiased.
+    fn furnace(roulette_depth: u32) -> f32 {
+        let material = EnumMaterialPod::new_lambertian(vec3(1.0, 1.0, 1.0));
+        let mut world: Vec<EnumPrimitivePod> = (0..8)
+            .map(|i| {
+                let corner = vec3((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32);
+                EnumPrimitivePod::new_sphere(2.1 * corner - Vec3::splat(1.05), 1.0, material)
+            })
+            .collect();
+        let mut rng = StdRng::seed_from_u64(0);
+        let bvh = create_bvh(&mut world, &[], 0.0, 1.0, BVHBuilder::Sah, 1, &mut rng).unwrap();
 
-    out[((constants.height - y - 1) * constants.width + x) as usize] += color.extend(1.0);
+        let primitives: Vec<EnumPrimitive> = world.into_iter().map(Into::into).collect();
+        let bvh: Vec<bvh::BVHNode> = bvh.into_iter().map(Into::into).collect();
+        let world = World {
+            primitives: &primitives,
+            vertices: &[],
+            instances: &[],
+            textures: Textures {
+                records: &[],
+                texels: &[],
+            },
+            lights: &[],
+            n_lights: 0,
+        };
+        let background: EnumBackground = BackgroundPod::new_solid(vec3(1.0, 1.0, 1.0)).into();
+
+        let n_samples = 100_000;
+        let mut rng = DefaultRng::new(1);
+        let sum: f64 = (0..n_samples)
+            .map(|_| {
+                let target = vec3(
+                    rng.next_f32_range(-1.0, 1.0),
+                    rng.next_f32_range(-1.0, 1.0),
+                    rng.next_f32_range(-1.0, 1.0),
+                );
+                let origin = vec3(0.3, 0.4, 5.0);
+                let ray = Ray {
+                    origin,
+                    direction: target - origin,
+                    time: 0.0,
+                };
+                ray_color(ray, &world, &bvh, &background, roulette_depth, &mut rng).x as f64
+            })
+            .sum();
+        (sum / n_samples as f64) as f32
+    }
+
+    #[test]
+    fn white_furnace_without_roulette() {
+        let mean = furnace(u32::MAX);
+        assert!((mean - 1.0).abs() < 1e-3, "{}", mean);
+    }
+
+    #[test]
+    fn white_furnace_with_roulette() {
+        // From the first bounce, so that every path goes through it
+        let mean = furnace(0);
+        assert!((mean - 1.0).abs() < 0.01, "{}", mean);
+    }
 }
@@ -0,0 +1,200 @@
+use spirv_std::glam::Vec3;
+
+use crate::{rand::DefaultRng, ray::Ray};
+
+/// A primitive that next event estimation can sample directions towards
+pub trait Light {
+    /// Density, per unit solid angle, of `random` returning the direction of `ray` from its
+    /// origin. 0 if the ray misses the light
+    fn pdf_value(&self, ray: &Ray) -> f32;
+
+    /// A direction from `origin` towards the light at `time`, not normalized
+    fn random(&self, origin: Vec3, time: f32, rng: &mut DefaultRng) -> Vec3;
+}
+
+#[cfg(test)]
+mod tests {
+    use spirv_std::glam::{uvec4, vec3, Vec4};
+
+    use super::*;
+    use crate::{
+        material::EnumMaterial,
+        quad::{Quad, Rect},
+        sphere::Sphere,
+        triangle::{MeshTriangle, Vertex},
+    };
+
+    const N_SAMPLES: usize = 100_000;
+
+    /// Mean of `1 / pdf_value` over the directions `random` samples from `origin`. It is the
+    /// solid angle of the light when `pdf_value` is the density of `random`.
+    fn mean_inverse_pdf(light: &impl Light, origin: Vec3) -> f32 {
+        let mut rng = DefaultRng::new(1);
+        let mut sum = 0.0f64;
+        for _ in 0..N_SAMPLES {
+            let ray = Ray {
+                origin,
+                direction: light.random(origin, 0.0, &mut rng),
+                time: 0.0,
+            };
+            let pdf = light.pdf_value(&ray);
+            assert!(pdf > 0.0, "a sample towards {} misses", ray.direction);
+            sum += 1.0 / pdf as f64;
+        }
+        (sum / N_SAMPLES as f64) as f32
+    }
+
+    /// Solid angle of the triangle `a`, `b`, `c` seen from `origin`, by Van Oosterom and
+    /// Strackee
+    fn triangle_solid_angle(origin: Vec3, a: Vec3, b: Vec3, c: Vec3) -> f32 {
+        let (a, b, c) = (a - origin, b - origin, c - origin);
+        let (la, lb, lc) = (a.length(), b.length(), c.length());
+        let numerator = a.dot(b.cross(c)).abs();
+        let denominator = la * lb * lc + a.dot(b) * lc + a.dot(c) * lb + b.dot(c) * la;
+        2.0 * numerator.atan2(denominator)
+    }
+
+    fn quad_solid_angle(origin: Vec3, quad: &Quad) -> f32 {
+        let (p0, p1, p2, p3) = (
+            quad.q,
+            quad.q + quad.u,
+            quad.q + quad.u + quad.v,
+            quad.q + quad.v,
+        );
+        triangle_solid_angle(origin, p0, p1, p2) + triangle_solid_angle(origin, p0, p2, p3)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.01 * expected,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn sphere() -> Sphere {
+        Sphere {
+            center: vec3(0.5, 0.2, -3.0),
+            radius: 1.0,
+            matelial: EnumMaterial::default(),
+        }
+    }
+
+    fn quad() -> Quad {
+        Quad {
+            q: vec3(-1.0, -0.5, -2.0),
+            u: vec3(2.0, 0.0, 0.5),
+            v: vec3(0.0, 1.5, 0.3),
+            material: EnumMaterial::default(),
+        }
+    }
+
+    fn rect() -> Rect {
+        Rect {
+            axis: 1,
+            minimum: vec3(-1.0, 2.0, -1.5),
+            maximum: vec3(0.5, 2.0, 0.5),
+            material: EnumMaterial::default(),
+        }
+    }
+
+    fn vertices() -> [Vertex; 3] {
+        let vertex = |x, y, z| Vertex {
+            position: Vec4::new(x, y, z, 0.0),
+            normal: Vec4::ZERO,
+        };
+        [
+            vertex(-1.0, 0.0, -2.0),
+            vertex(1.0, 0.2, -2.5),
+            vertex(0.0, 1.5, -1.8),
+        ]
+    }
+
+    fn triangle(vertices: &[Vertex]) -> MeshTriangle<'_> {
+        MeshTriangle {
+            indices: uvec4(0, 1, 2, 0),
+            material: EnumMaterial::default(),
+            vertices,
+        }
+    }
+
+    #[test]
+    fn sphere_density_matches_its_cone() {
+        let sphere = sphere();
+        let origin = vec3(0.1, -0.2, 0.3);
+        let distance_squared = (sphere.center - origin).length_squared();
+        let cos_theta_max = (1.0 - sphere.radius * sphere.radius / distance_squared).sqrt();
+        let expected = 2.0 * core::f32::consts::PI * (1.0 - cos_theta_max);
+        assert_close(mean_inverse_pdf(&sphere, origin), expected);
+    }
+
+    #[test]
+    fn quad_density_matches_its_solid_angle() {
+        let quad = quad();
+        let origin = vec3(0.2, 0.1, 0.3);
+        assert_close(
+            mean_inverse_pdf(&quad, origin),
+            quad_solid_angle(origin, &quad),
+        );
+    }
+
+    #[test]
+    fn rect_density_matches_its_solid_angle() {
+        let rect = rect();
+        let origin = vec3(0.3, 0.0, 0.2);
+        let (a, b) = (rect.minimum, rect.maximum);
+        let corners = Quad {
+            q: a,
+            u: vec3(b.x - a.x, 0.0, 0.0),
+            v: vec3(0.0, 0.0, b.z - a.z),
+            material: EnumMaterial::default(),
+        };
+        assert_close(
+            mean_inverse_pdf(&rect, origin),
+            quad_solid_angle(origin, &corners),
+        );
+    }
+
+    #[test]
+    fn triangle_density_matches_its_solid_angle() {
+        let vertices = vertices();
+        let triangle = triangle(&vertices);
+        let origin = vec3(0.1, 0.4, 0.5);
+        let p = |i: usize| vertices[i].position.truncate();
+        assert_close(
+            mean_inverse_pdf(&triangle, origin),
+            triangle_solid_angle(origin, p(0), p(1), p(2)),
+        );
+    }
+
+    #[test]
+    fn density_is_zero_for_rays_that_miss() {
+        let vertices = vertices();
+        let origin = Vec3::ZERO;
+        // Every light is in front of or above the origin
+        let away = |direction| Ray {
+            origin,
+            direction,
+            time: 0.0,
+        };
+        let aside = away(vec3(1.0, -1.0, 0.0));
+        let behind = away(vec3(0.0, -1.0, 1.0));
+
+        for ray in &[aside, behind] {
+            assert_eq!(sphere().pdf_value(ray), 0.0);
+            assert_eq!(quad().pdf_value(ray), 0.0);
+            assert_eq!(rect().pdf_value(ray), 0.0);
+            assert_eq!(triangle(&vertices).pdf_value(ray), 0.0);
+        }
+
+        // The sphere subtends no cone from inside
+        let sphere = sphere();
+        let inside = Ray {
+            origin: sphere.center,
+            direction: vec3(0.0, 0.0, 1.0),
+            time: 0.0,
+        };
+        assert_eq!(sphere.pdf_value(&inside), 0.0);
+    }
+}
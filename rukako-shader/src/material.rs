@@ -1,29 +1,65 @@
 use spirv_std::glam::{vec3, Vec3, Vec4, Vec4Swizzles};
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
+use spirv_std::num_traits::FloatConst;
 
 use crate::{
     bool::Bool32,
     hittable::HitRecord,
-    math::{random_in_unit_sphere, IsNearZero},
+    math::{random_cosine_direction, random_in_unit_sphere},
+    onb::Onb,
     rand::DefaultRng,
     ray::Ray,
+    texture::Textures,
 };
 
+/// A direction chosen by `Material::sample`
 #[derive(Clone, Default)]
 pub struct Scatter {
+    /// `eval / pdf` of the direction, by which the path is attenuated
     pub color: Vec3,
     pub ray: Ray,
+    /// Density of the direction per solid angle, or 0 for delta lobes
+    pub pdf: f32,
+    /// Whether the direction was chosen by a delta lobe, such as a mirror reflection or a
+    /// refraction, which `eval` and `pdf` do not cover
+    pub delta: Bool32,
 }
 
+/// Scattering following "Ray Tracing: The Rest of Your Life". `sample` chooses the direction
+/// a path continues in, and `eval` and `pdf` give the scattering towards and the density of
+/// any other direction, such as one towards a light.
 pub trait Material {
-    fn scatter(
+    /// Returns false when the path is absorbed
+    fn sample(
         &self,
         ray: &Ray,
         hit_record: &HitRecord,
+        textures: &Textures,
         rng: &mut DefaultRng,
         scatter: &mut Scatter,
     ) -> Bool32;
+
+    /// The BSDF times the cosine of `direction` with the normal, or the phase function in a
+    /// medium. 0 for delta lobes.
+    fn eval(
+        &self,
+        _ray: &Ray,
+        _hit_record: &HitRecord,
+        _textures: &Textures,
+        _direction: Vec3,
+    ) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    /// The density of `sample` choosing `direction`. 0 for delta lobes.
+    fn pdf(&self, _ray: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    fn emitted(&self, _hit_record: &HitRecord) -> Vec3 {
+        Vec3::ZERO
+    }
 }
 
 #[derive(Clone, Copy, Default)]
@@ -37,20 +73,42 @@ struct EnumMaterialData {
 pub struct EnumMaterial {
     data: EnumMaterialData,
     t: u32,
+    /// Modulates the albedo of lambertian and metal materials, see `Textures::modulate`
+    texture: u32,
+}
+
+impl EnumMaterial {
+    pub fn new(v0: Vec4, t: u32, texture: u32) -> Self {
+        Self {
+            data: EnumMaterialData { v0 },
+            t,
+            texture,
+        }
+    }
 }
 
 struct Lambertian<'a> {
     data: &'a EnumMaterialData,
+    texture: u32,
 }
 
 struct Metal<'a> {
     data: &'a EnumMaterialData,
+    texture: u32,
 }
 
 struct Dielectric<'a> {
     data: &'a EnumMaterialData,
 }
 
+struct DiffuseLight<'a> {
+    data: &'a EnumMaterialData,
+}
+
+struct Isotropic<'a> {
+    data: &'a EnumMaterialData,
+}
+
 fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - 2.0 * v.dot(n) * n
 }
@@ -69,44 +127,54 @@ fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
 }
 
 impl<'a> Lambertian<'a> {
-    fn albedo(&self) -> Vec3 {
-        self.data.v0.xyz()
+    fn albedo(&self, hit_record: &HitRecord, textures: &Textures) -> Vec3 {
+        textures.modulate(self.data.v0.xyz(), self.texture, hit_record)
     }
 }
 
 impl<'a> Material for Lambertian<'a> {
-    fn scatter(
+    /// Cosine-weighted directions about the normal
+    fn sample(
         &self,
         ray: &Ray,
         hit_record: &HitRecord,
+        textures: &Textures,
         rng: &mut DefaultRng,
         scatter: &mut Scatter,
     ) -> Bool32 {
-        let scatter_direction = hit_record.normal + random_in_unit_sphere(rng).normalize();
-
-        let scatter_direction = if scatter_direction.is_near_zero().into() {
-            hit_record.normal
-        } else {
-            scatter_direction
-        };
-
-        let scatterd = Ray {
-            origin: hit_record.position,
-            direction: scatter_direction,
-            time: ray.time,
-        };
+        let direction = Onb::from_w(hit_record.normal).local(random_cosine_direction(rng));
 
         *scatter = Scatter {
-            color: self.albedo(),
-            ray: scatterd,
+            color: self.albedo(hit_record, textures),
+            ray: Ray {
+                origin: hit_record.position,
+                direction,
+                time: ray.time,
+            },
+            pdf: self.pdf(ray, hit_record, direction),
+            delta: Bool32::FALSE,
         };
         Bool32::TRUE
     }
+
+    fn eval(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        textures: &Textures,
+        direction: Vec3,
+    ) -> Vec3 {
+        self.albedo(hit_record, textures) * self.pdf(ray, hit_record, direction)
+    }
+
+    fn pdf(&self, _ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f32 {
+        hit_record.normal.dot(direction.normalize()).max(0.0) / f32::PI()
+    }
 }
 
 impl<'a> Metal<'a> {
-    fn albedo(&self) -> Vec3 {
-        self.data.v0.xyz()
+    fn albedo(&self, hit_record: &HitRecord, textures: &Textures) -> Vec3 {
+        textures.modulate(self.data.v0.xyz(), self.texture, hit_record)
     }
 
     fn fuzz(&self) -> f32 {
@@ -115,10 +183,11 @@ impl<'a> Metal<'a> {
 }
 
 impl<'a> Material for Metal<'a> {
-    fn scatter(
+    fn sample(
         &self,
         ray: &Ray,
         hit_record: &HitRecord,
+        textures: &Textures,
         rng: &mut DefaultRng,
         scatter: &mut Scatter,
     ) -> Bool32 {
@@ -126,12 +195,14 @@ impl<'a> Material for Metal<'a> {
         let scatterd = reflected + self.fuzz() * random_in_unit_sphere(rng);
         if scatterd.dot(hit_record.normal) > 0.0 {
             *scatter = Scatter {
-                color: self.albedo(),
+                color: self.albedo(hit_record, textures),
                 ray: Ray {
                     origin: hit_record.position,
                     direction: scatterd,
                     time: ray.time,
                 },
+                pdf: 0.0,
+                delta: Bool32::TRUE,
             };
             Bool32::TRUE
         } else {
@@ -147,10 +218,11 @@ impl<'a> Dielectric<'a> {
 }
 
 impl<'a> Material for Dielectric<'a> {
-    fn scatter(
+    fn sample(
         &self,
         ray: &Ray,
         hit_record: &HitRecord,
+        _textures: &Textures,
         rng: &mut DefaultRng,
         scatter: &mut Scatter,
     ) -> Bool32 {
@@ -183,23 +255,278 @@ impl<'a> Material for Dielectric<'a> {
                 direction,
                 time: ray.time,
             },
+            pdf: 0.0,
+            delta: Bool32::TRUE,
         };
         Bool32::TRUE
     }
 }
 
+impl<'a> DiffuseLight<'a> {
+    fn emit(&self) -> Vec3 {
+        self.data.v0.xyz()
+    }
+}
+
+impl<'a> Material for DiffuseLight<'a> {
+    fn sample(
+        &self,
+        _ray: &Ray,
+        _hit_record: &HitRecord,
+        _textures: &Textures,
+        _rng: &mut DefaultRng,
+        _scatter: &mut Scatter,
+    ) -> Bool32 {
+        Bool32::FALSE
+    }
+
+    fn emitted(&self, _hit_record: &HitRecord) -> Vec3 {
+        self.emit()
+    }
+}
+
+impl<'a> Isotropic<'a> {
+    fn albedo(&self) -> Vec3 {
+        self.data.v0.xyz()
+    }
+}
+
+impl<'a> Material for Isotropic<'a> {
+    /// Scatters uniformly in all directions, the phase function of a constant medium
+    fn sample(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        _textures: &Textures,
+        rng: &mut DefaultRng,
+        scatter: &mut Scatter,
+    ) -> Bool32 {
+        let direction = random_in_unit_sphere(rng).normalize();
+        *scatter = Scatter {
+            color: self.albedo(),
+            ray: Ray {
+                origin: hit_record.position,
+                direction,
+                time: ray.time,
+            },
+            pdf: self.pdf(ray, hit_record, direction),
+            delta: Bool32::FALSE,
+        };
+        Bool32::TRUE
+    }
+
+    fn eval(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        _textures: &Textures,
+        direction: Vec3,
+    ) -> Vec3 {
+        self.albedo() * self.pdf(ray, hit_record, direction)
+    }
+
+    fn pdf(&self, _ray: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> f32 {
+        0.25 * f32::FRAC_1_PI()
+    }
+}
+
 impl Material for EnumMaterial {
-    fn scatter(
+    fn sample(
         &self,
         ray: &Ray,
         hit_record: &HitRecord,
+        textures: &Textures,
         rng: &mut DefaultRng,
         scatter: &mut Scatter,
     ) -> Bool32 {
         match self.t {
-            0 => Lambertian { data: &self.data }.scatter(ray, hit_record, rng, scatter),
-            1 => Metal { data: &self.data }.scatter(ray, hit_record, rng, scatter),
-            _ => Dielectric { data: &self.data }.scatter(ray, hit_record, rng, scatter),
+            0 => Lambertian {
+                data: &self.data,
+                texture: self.texture,
+            }
+            .sample(ray, hit_record, textures, rng, scatter),
+            1 => Metal {
+                data: &self.data,
+                texture: self.texture,
+            }
+            .sample(ray, hit_record, textures, rng, scatter),
+            2 => Dielectric { data: &self.data }.sample(ray, hit_record, textures, rng, scatter),
+            3 => DiffuseLight { data: &self.data }.sample(ray, hit_record, textures, rng, scatter),
+            _ => Isotropic { data: &self.data }.sample(ray, hit_record, textures, rng, scatter),
+        }
+    }
+
+    fn eval(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        textures: &Textures,
+        direction: Vec3,
+    ) -> Vec3 {
+        match self.t {
+            0 => Lambertian {
+                data: &self.data,
+                texture: self.texture,
+            }
+            .eval(ray, hit_record, textures, direction),
+            4 => Isotropic { data: &self.data }.eval(ray, hit_record, textures, direction),
+            _ => Vec3::ZERO,
+        }
+    }
+
+    fn pdf(&self, ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f32 {
+        match self.t {
+            0 => Lambertian {
+                data: &self.data,
+                texture: self.texture,
+            }
+            .pdf(ray, hit_record, direction),
+            4 => Isotropic { data: &self.data }.pdf(ray, hit_record, direction),
+            _ => 0.0,
+        }
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Vec3 {
+        match self.t {
+            3 => DiffuseLight { data: &self.data }.emitted(hit_record),
+            _ => Vec3::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spirv_std::glam::vec3;
+
+    use super::*;
+    use crate::pod::EnumMaterialPod;
+
+    const N_SAMPLES: usize = 100_000;
+
+    const TEXTURES: Textures = Textures {
+        records: &[],
+        texels: &[],
+    };
+
+    fn lambertian() -> EnumMaterial {
+        EnumMaterialPod::new_lambertian(vec3(0.8, 0.5, 0.2)).into()
+    }
+
+    fn isotropic() -> EnumMaterial {
+        EnumMaterialPod::new_isotropic(vec3(0.3, 0.6, 0.9)).into()
+    }
+
+    /// A ray coming down onto a tilted surface at the origin
+    fn hit(material: EnumMaterial) -> (Ray, HitRecord) {
+        let ray = Ray {
+            origin: vec3(0.3, 2.0, 0.5),
+            direction: vec3(-0.3, -2.0, -0.5),
+            time: 0.0,
+        };
+        let hit_record = HitRecord::new(
+            Vec3::ZERO,
+            vec3(0.2, 1.0, -0.4).normalize(),
+            1.0,
+            &ray,
+            material,
+        );
+        (ray, hit_record)
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance * expected.abs(),
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn assert_consistent(material: EnumMaterial) {
+        let (ray, hit_record) = hit(material);
+        let mut rng = DefaultRng::new(1);
+        let mut scatter = Scatter::default();
+        for _ in 0..1000 {
+            let sampled = material.sample(&ray, &hit_record, &TEXTURES, &mut rng, &mut scatter);
+            assert!(sampled == Bool32::TRUE);
+            assert!(scatter.delta == Bool32::FALSE);
+            let direction = scatter.ray.direction;
+            let pdf = material.pdf(&ray, &hit_record, direction);
+            assert_close(scatter.pdf, pdf, 1e-5);
+            let eval = material.eval(&ray, &hit_record, &TEXTURES, direction);
+            // Directions grazing the surface have no density to divide by
+            if pdf > 1e-3 {
+                for (f, color) in <[f32; 3]>::from(eval / pdf)
+                    .iter()
+                    .zip(<[f32; 3]>::from(scatter.color).iter())
+                {
+                    assert_close(*f, *color, 1e-4);
+                }
+            }
+        }
+    }
+
+    /// Monte Carlo estimate of the integral of `pdf` over all directions
+    fn integrate_pdf(material: EnumMaterial) -> f32 {
+        let (ray, hit_record) = hit(material);
+        let mut rng = DefaultRng::new(2);
+        let sum: f64 = (0..N_SAMPLES)
+            .map(|_| {
+                let direction = random_in_unit_sphere(&mut rng).normalize();
+                material.pdf(&ray, &hit_record, direction) as f64
+            })
+            .sum();
+        (4.0 * std::f64::consts::PI * sum / N_SAMPLES as f64) as f32
+    }
+
+    fn assert_delta(material: EnumMaterial) {
+        let (ray, hit_record) = hit(material);
+        let mut rng = DefaultRng::new(3);
+        let mut scatter = Scatter::default();
+        for _ in 0..100 {
+            if material
+                .sample(&ray, &hit_record, &TEXTURES, &mut rng, &mut scatter)
+                .into()
+            {
+                assert!(scatter.delta == Bool32::TRUE);
+                assert_eq!(scatter.pdf, 0.0);
+                let direction = scatter.ray.direction;
+                assert_eq!(material.pdf(&ray, &hit_record, direction), 0.0);
+                assert_eq!(
+                    material.eval(&ray, &hit_record, &TEXTURES, direction),
+                    Vec3::ZERO
+                );
+            }
         }
     }
+
+    #[test]
+    fn lambertian_samples_match_its_pdf_and_eval() {
+        assert_consistent(lambertian());
+    }
+
+    #[test]
+    fn isotropic_samples_match_its_pdf_and_eval() {
+        assert_consistent(isotropic());
+    }
+
+    #[test]
+    fn lambertian_pdf_integrates_to_one() {
+        assert_close(integrate_pdf(lambertian()), 1.0, 0.02);
+    }
+
+    #[test]
+    fn isotropic_pdf_integrates_to_one() {
+        assert_close(integrate_pdf(isotropic()), 1.0, 1e-5);
+    }
+
+    #[test]
+    fn metal_is_a_delta_lobe() {
+        assert_delta(EnumMaterialPod::new_metal(vec3(0.9, 0.9, 0.9), 0.3).into());
+    }
+
+    #[test]
+    fn dielectric_is_a_delta_lobe() {
+        assert_delta(EnumMaterialPod::new_dielectric(1.5).into());
+    }
 }
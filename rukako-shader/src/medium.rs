@@ -0,0 +1,139 @@
+use core::mem::swap;
+use spirv_std::glam::{vec3, Vec3};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+
+use crate::{
+    aabb::AABB,
+    bool::Bool32,
+    hittable::{HitRecord, Hittable},
+    material::EnumMaterial,
+    rand::DefaultRng,
+    ray::Ray,
+};
+
+/// A volume of constant density filling a convex `boundary`. Rays travel through it for a
+/// random distance and scatter by `phase_function`, usually an isotropic material.
+pub struct ConstantMedium<B> {
+    pub boundary: B,
+    pub neg_inv_density: f32,
+    pub phase_function: EnumMaterial,
+}
+
+impl<B: Hittable> ConstantMedium<B> {
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit_record: &mut HitRecord,
+        rng: &mut DefaultRng,
+    ) -> Bool32 {
+        // Where the line of the ray enters and leaves the boundary
+        let mut enter = HitRecord::default();
+        if (!self
+            .boundary
+            .hit(ray, f32::NEG_INFINITY, f32::INFINITY, &mut enter))
+        .into()
+        {
+            return Bool32::FALSE;
+        }
+        let mut exit = HitRecord::default();
+        if (!self
+            .boundary
+            .hit(ray, enter.t + 0.0001, f32::INFINITY, &mut exit))
+        .into()
+        {
+            return Bool32::FALSE;
+        }
+
+        let t0 = enter.t.max(t_min).max(0.0);
+        let t1 = exit.t.min(t_max);
+        if t0 >= t1 {
+            return Bool32::FALSE;
+        }
+
+        // Free-flight distance, exponentially distributed with the density as its rate
+        let ray_length = ray.direction.length();
+        let distance_inside = (t1 - t0) * ray_length;
+        let hit_distance = self.neg_inv_density * rng.next_f32().ln();
+        if hit_distance > distance_inside {
+            return Bool32::FALSE;
+        }
+
+        let t = t0 + hit_distance / ray_length;
+        *hit_record = HitRecord::new(
+            ray.at(t),
+            // Arbitrary, the phase function does not depend on it
+            -ray.direction.normalize(),
+            t,
+            ray,
+            self.phase_function,
+        );
+        Bool32::TRUE
+    }
+
+    pub fn bounding_box(&self, time0: f32, time1: f32) -> AABB {
+        self.boundary.bounding_box(time0, time1)
+    }
+}
+
+/// The solid box from `minimum` to `maximum`, as the boundary of a medium
+pub struct BoxBoundary {
+    pub minimum: Vec3,
+    pub maximum: Vec3,
+}
+
+impl Hittable for BoxBoundary {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, hit_record: &mut HitRecord) -> Bool32 {
+        let inv_d = vec3(1.0, 1.0, 1.0) / ray.direction;
+        let mut t0 = (self.minimum - ray.origin) * inv_d;
+        let mut t1 = (self.maximum - ray.origin) * inv_d;
+        if inv_d.x < 0.0 {
+            swap(&mut t0.x, &mut t1.x);
+        }
+        if inv_d.y < 0.0 {
+            swap(&mut t0.y, &mut t1.y);
+        }
+        if inv_d.z < 0.0 {
+            swap(&mut t0.z, &mut t1.z);
+        }
+
+        let enter = t0.x.max(t0.y).max(t0.z);
+        let exit = t1.x.min(t1.y).min(t1.z);
+        if enter > exit {
+            return Bool32::FALSE;
+        }
+
+        // The first of the two crossings within the range
+        let t = if enter >= t_min { enter } else { exit };
+        if Bool32::new(t < t_min).or(Bool32::new(t_max < t)).into() {
+            return Bool32::FALSE;
+        }
+
+        let position = ray.at(t);
+        let center = 0.5 * (self.minimum + self.maximum);
+        let half = 0.5 * (self.maximum - self.minimum);
+        let d = (position - center) / half;
+        let outward_normal = if Bool32::new(d.x.abs() >= d.y.abs())
+            .and(Bool32::new(d.x.abs() >= d.z.abs()))
+            .into()
+        {
+            vec3(d.x.signum(), 0.0, 0.0)
+        } else if d.y.abs() >= d.z.abs() {
+            vec3(0.0, d.y.signum(), 0.0)
+        } else {
+            vec3(0.0, 0.0, d.z.signum())
+        };
+
+        *hit_record = HitRecord::new(position, outward_normal, t, ray, EnumMaterial::default());
+        Bool32::TRUE
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> AABB {
+        AABB {
+            minimum: self.minimum,
+            maximum: self.maximum,
+        }
+    }
+}
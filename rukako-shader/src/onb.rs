@@ -0,0 +1,29 @@
+use spirv_std::glam::{vec3, Vec3};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+
+/// Orthonormal basis whose `w` axis is a given direction
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(w: Vec3) -> Self {
+        let w = w.normalize();
+        let a = if w.x.abs() > 0.9 {
+            vec3(0.0, 1.0, 0.0)
+        } else {
+            vec3(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).normalize();
+        let u = w.cross(v);
+        Self { u, v, w }
+    }
+
+    /// The vector of coordinates `a` in this basis
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+}
@@ -1,23 +1,58 @@
-use crate::aabb::{surrounding_box, AABB};
+use core::{fmt, ops::Range};
+use std::collections::BTreeMap;
+
+use crate::{
+    aabb::{surrounding_box, AABB},
+    bvh::{BVHNode, STACK_SIZE},
+};
 use bytemuck::{Pod, Zeroable};
 use float_ord::FloatOrd;
 use rand::prelude::*;
-use spirv_std::glam::Vec3;
+use spirv_std::glam::{uvec4, vec3, Vec3};
+
+use super::{EnumPrimitivePod, InstancePod, VertexPod};
+
+/// How `create_bvh` splits the primitives of a node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BVHBuilder {
+    /// Binned surface area heuristic
+    Sah,
+    /// Median split along a random axis
+    RandomMedian,
+}
+
+impl Default for BVHBuilder {
+    fn default() -> Self {
+        BVHBuilder::Sah
+    }
+}
 
-use super::SpherePod;
+/// Leaf size used when nothing else is asked for
+pub const DEFAULT_MAX_LEAF_SIZE: usize = 4;
+
+const SAH_BINS: usize = 16;
 
 #[derive(Clone, Copy, Debug, Zeroable, Pod)]
 #[repr(C)]
 pub struct BVHNodePod {
     minimum: [f32; 4],
     maximum: [f32; 4],
-    child: [u32; 4],
+    pub(crate) child: [u32; 4],
 }
 
 enum BVHChildInner {
     One(usize),
     Two(usize, usize),
-    World(usize),
+    /// A range of `count` primitives starting at `first`
+    World {
+        first: usize,
+        count: usize,
+    },
+    /// A range of `count` instances starting at `first`
+    Instances {
+        first: usize,
+        count: usize,
+    },
 }
 
 struct BVHNodeInner {
@@ -25,61 +60,288 @@ struct BVHNodeInner {
     child: BVHChildInner,
 }
 
-fn create_bvh_inner(
-    world: &mut [SpherePod],
-    time0: f32,
-    time1: f32,
-    l: usize,
-    r: usize,
-    out: &mut Vec<BVHNodeInner>,
-    rng: &mut impl Rng,
-) -> Option<usize> {
-    if !(l < r) {
-        return None;
+/// Bounding boxes of the primitives followed by those of the instances, sorted into leaves
+/// by permuting `order`
+struct Primitives {
+    boxes: Vec<AABB>,
+    /// Position in the leaves -> index into `boxes`
+    order: Vec<usize>,
+    /// Boxes from this index on belong to instances
+    first_instance: usize,
+}
+
+impl Primitives {
+    fn new(boxes: Vec<AABB>, first_instance: usize) -> Self {
+        let order = (0..boxes.len()).collect();
+        Self {
+            boxes,
+            order,
+            first_instance,
+        }
+    }
+
+    fn aabb(&self, k: usize) -> &AABB {
+        &self.boxes[self.order[k]]
+    }
+
+    fn is_instance(&self, k: usize) -> bool {
+        self.order[k] >= self.first_instance
+    }
+
+    /// Reorders `world` and `instances` to match the leaves. Returns where each position of
+    /// the leaves ends up in the buffer it belongs to.
+    fn apply<T: Copy, U: Copy>(&self, world: &mut [T], instances: &mut [U]) -> Vec<usize> {
+        let mut sorted_world = Vec::with_capacity(world.len());
+        let mut sorted_instances = Vec::with_capacity(instances.len());
+        let positions = self
+            .order
+            .iter()
+            .map(|&i| {
+                if i < self.first_instance {
+                    sorted_world.push(world[i]);
+                    sorted_world.len() - 1
+                } else {
+                    sorted_instances.push(instances[i - self.first_instance]);
+                    sorted_instances.len() - 1
+                }
+            })
+            .collect();
+        world.copy_from_slice(&sorted_world);
+        instances.copy_from_slice(&sorted_instances);
+        positions
     }
+}
+
+struct Builder {
+    max_leaf_size: usize,
+    out: Vec<BVHNodeInner>,
+}
+
+impl Builder {
+    fn push_leaf(&mut self, primitives: &mut Primitives, l: usize, r: usize) -> usize {
+        // A leaf holds either primitives or instances, so split mixed ranges. The sort is
+        // stable and leaves ranges of a single kind alone.
+        let first_instance = primitives.first_instance;
+        primitives.order[l..r].sort_by_key(|&i| i >= first_instance);
+        let mid = (l..r).find(|&k| primitives.is_instance(k)).unwrap_or(r);
+        if l < mid && mid < r {
+            let i = self.push_dummy();
+            let left = self.push_leaf(primitives, l, mid);
+            let right = self.push_leaf(primitives, mid, r);
+            self.out[i] = self.inner_node(Some(left), Some(right));
+            return i;
+        }
 
-    if r - l == 1 {
-        let i = out.len();
-        out.push(BVHNodeInner {
-            aabb: world[l].bounding_box(time0, time1),
-            child: BVHChildInner::World(l),
+        let aabb = (l + 1..r).fold(*primitives.aabb(l), |aabb, k| {
+            surrounding_box(aabb, *primitives.aabb(k))
         });
-        return Some(i);
+
+        let (first, count) = (l, r - l);
+        let child = if primitives.is_instance(l) {
+            BVHChildInner::Instances { first, count }
+        } else {
+            BVHChildInner::World { first, count }
+        };
+
+        let i = self.out.len();
+        self.out.push(BVHNodeInner { aabb, child });
+        i
     }
 
-    let axis = rng.gen_range(0..=2);
+    /// Reserves the slot of an inner node so that it precedes its children
+    fn push_dummy(&mut self) -> usize {
+        let dummy_child = BVHNodeInner {
+            aabb: AABB {
+                minimum: Vec3::ZERO,
+                maximum: Vec3::ZERO,
+            },
+            child: BVHChildInner::One(0),
+        };
 
-    world[l..r].sort_by_key(|w| FloatOrd(w.bounding_box(time0, time1).minimum[axis]));
+        let i = self.out.len();
+        self.out.push(dummy_child);
+        i
+    }
 
-    let dummy_child = BVHNodeInner {
-        aabb: AABB {
-            minimum: Vec3::ZERO,
-            maximum: Vec3::ZERO,
-        },
-        child: BVHChildInner::One(0),
-    };
+    fn build(
+        &mut self,
+        builder: BVHBuilder,
+        primitives: &mut Primitives,
+        rng: &mut impl Rng,
+    ) -> Option<usize> {
+        let n = primitives.boxes.len();
+        match builder {
+            BVHBuilder::Sah => self.sah(primitives, 0, n),
+            BVHBuilder::RandomMedian => self.median(primitives, 0, n, rng),
+        }
+    }
+
+    fn median(
+        &mut self,
+        primitives: &mut Primitives,
+        l: usize,
+        r: usize,
+        rng: &mut impl Rng,
+    ) -> Option<usize> {
+        if !(l < r) {
+            return None;
+        }
 
-    let i = out.len();
-    out.push(dummy_child);
+        if r - l <= self.max_leaf_size {
+            return Some(self.push_leaf(primitives, l, r));
+        }
 
-    let mid = (l + r) / 2;
+        let axis = rng.gen_range(0..=2);
 
-    let left = create_bvh_inner(world, time0, time1, l, mid, out, rng);
-    let right = create_bvh_inner(world, time0, time1, mid, r, out, rng);
+        let boxes = &primitives.boxes;
+        primitives.order[l..r].sort_by_key(|&i| FloatOrd(boxes[i].minimum[axis]));
 
-    out[i] = match (left, right) {
-        (Some(left), Some(right)) => BVHNodeInner {
-            aabb: surrounding_box(out[left].aabb, out[right].aabb),
-            child: BVHChildInner::Two(left, right),
-        },
-        (Some(left), None) => BVHNodeInner {
-            aabb: out[left].aabb,
-            child: BVHChildInner::One(left),
-        },
-        _ => unreachable!(),
-    };
+        let i = self.push_dummy();
+
+        let mid = (l + r) / 2;
+
+        let left = self.median(primitives, l, mid, rng);
+        let right = self.median(primitives, mid, r, rng);
+
+        self.out[i] = self.inner_node(left, right);
+
+        Some(i)
+    }
+
+    fn sah(&mut self, primitives: &mut Primitives, l: usize, r: usize) -> Option<usize> {
+        if !(l < r) {
+            return None;
+        }
+
+        if r - l <= self.max_leaf_size {
+            return Some(self.push_leaf(primitives, l, r));
+        }
+
+        let boxes: Vec<AABB> = (l..r).map(|k| *primitives.aabb(k)).collect();
+        let (c_min, c_max) = boxes.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(c_min, c_max), aabb| (c_min.min(centroid(aabb)), c_max.max(centroid(aabb))),
+        );
+
+        let mid = match best_sah_split(&boxes, c_min, c_max) {
+            Some((axis, split)) => {
+                let extent = c_max[axis] - c_min[axis];
+                // The bins holding the extreme centroids are never empty, so both sides
+                // are non-empty
+                let mut mid = l;
+                for (k, aabb) in (l..r).zip(boxes.iter()) {
+                    if bin_index(centroid(aabb)[axis], c_min[axis], extent) < split {
+                        primitives.order.swap(k, mid);
+                        mid += 1;
+                    }
+                }
+                mid
+            }
+            // Every centroid coincides, so no plane separates them
+            None => (l + r) / 2,
+        };
+
+        let i = self.push_dummy();
 
-    Some(i)
+        let left = self.sah(primitives, l, mid);
+        let right = self.sah(primitives, mid, r);
+
+        self.out[i] = self.inner_node(left, right);
+
+        Some(i)
+    }
+
+    fn inner_node(&self, left: Option<usize>, right: Option<usize>) -> BVHNodeInner {
+        let out = &self.out;
+        match (left, right) {
+            (Some(left), Some(right)) => {
+                // Nodes are in pre-order, so each subtree is a contiguous range. The
+                // traversal pops the second child first; make it the smaller one.
+                let (left_size, right_size) = (right - left, out.len() - right);
+                let child = if right_size <= left_size {
+                    BVHChildInner::Two(left, right)
+                } else {
+                    BVHChildInner::Two(right, left)
+                };
+                BVHNodeInner {
+                    aabb: surrounding_box(out[left].aabb, out[right].aabb),
+                    child,
+                }
+            }
+            (Some(left), None) => BVHNodeInner {
+                aabb: out[left].aabb,
+                child: BVHChildInner::One(left),
+            },
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn centroid(aabb: &AABB) -> Vec3 {
+    0.5 * (aabb.minimum + aabb.maximum)
+}
+
+fn surface_area(aabb: &AABB) -> f32 {
+    let d = aabb.maximum - aabb.minimum;
+    2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+}
+
+fn merge(a: Option<AABB>, b: AABB) -> Option<AABB> {
+    Some(match a {
+        Some(a) => surrounding_box(a, b),
+        None => b,
+    })
+}
+
+fn bin_index(c: f32, min: f32, extent: f32) -> usize {
+    (((c - min) / extent * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+}
+
+/// Returns the axis and the first bin of the right side of the cheapest split
+fn best_sah_split(boxes: &[AABB], c_min: Vec3, c_max: Vec3) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize, f32)> = None;
+
+    for axis in 0..3 {
+        let extent = c_max[axis] - c_min[axis];
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut counts = [0usize; SAH_BINS];
+        let mut bounds = [None; SAH_BINS];
+        for aabb in boxes {
+            let b = bin_index(centroid(aabb)[axis], c_min[axis], extent);
+            counts[b] += 1;
+            bounds[b] = merge(bounds[b], *aabb);
+        }
+
+        // Cost of everything at or right of each bin
+        let mut right_cost = [0.0; SAH_BINS];
+        let mut acc = None;
+        let mut n = 0;
+        for b in (1..SAH_BINS).rev() {
+            if let Some(aabb) = bounds[b] {
+                acc = merge(acc, aabb);
+            }
+            n += counts[b];
+            right_cost[b] = n as f32 * acc.as_ref().map_or(0.0, surface_area);
+        }
+
+        let mut acc = None;
+        let mut n = 0;
+        for b in 1..SAH_BINS {
+            if let Some(aabb) = bounds[b - 1] {
+                acc = merge(acc, aabb);
+            }
+            n += counts[b - 1];
+            let cost = n as f32 * acc.as_ref().map_or(0.0, surface_area) + right_cost[b];
+            if best.map_or(true, |(_, _, best_cost)| cost < best_cost) {
+                best = Some((axis, b, cost));
+            }
+        }
+    }
+
+    best.map(|(axis, b, _)| (axis, b))
 }
 
 impl Into<BVHNodePod> for BVHNodeInner {
@@ -87,7 +349,8 @@ impl Into<BVHNodePod> for BVHNodeInner {
         let child = match self.child {
             BVHChildInner::One(l) => [0, l as u32, 0, 0],
             BVHChildInner::Two(l, r) => [1, l as u32, r as u32, 0],
-            BVHChildInner::World(w) => [2, 0, 0, w as u32],
+            BVHChildInner::World { first, count } => [2, count as u32, 0, first as u32],
+            BVHChildInner::Instances { first, count } => [3, count as u32, 0, first as u32],
         };
         BVHNodePod {
             minimum: [
@@ -107,15 +370,691 @@ impl Into<BVHNodePod> for BVHNodeInner {
     }
 }
 
+/// The tree needs a deeper traversal stack than `bvh::BVH::hit` has
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackOverflow {
+    pub required: usize,
+    pub capacity: usize,
+}
+
+impl fmt::Display for StackOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BVH traversal needs a stack of {} entries but the shader has {}",
+            self.required, self.capacity
+        )
+    }
+}
+
+impl std::error::Error for StackOverflow {}
+
+/// Builds a BVH over `primitives`, reordering them so that every leaf references a
+/// contiguous range of at most `max_leaf_size` primitives. Triangles index `vertices`.
+/// The root is the first node.
 pub fn create_bvh(
-    world: &mut [SpherePod],
+    primitives: &mut [EnumPrimitivePod],
+    vertices: &[VertexPod],
     time0: f32,
     time1: f32,
+    builder: BVHBuilder,
+    max_leaf_size: usize,
     rng: &mut impl Rng,
-) -> Vec<BVHNodePod> {
-    let mut ret = Vec::new();
+) -> Result<Vec<BVHNodePod>, StackOverflow> {
+    create_instanced_bvh(
+        primitives,
+        &[],
+        &mut [],
+        vertices,
+        time0,
+        time1,
+        builder,
+        max_leaf_size,
+        rng,
+    )
+}
+
+/// Builds the two-level BVH of a scene. `primitives` starts with the primitives placed in
+/// world space, followed by `objects`, consecutive non-empty ranges of primitives that
+/// `instances` place in the world. The primitives of each part and the instances are
+/// reordered so that every leaf references a contiguous range of at most `max_leaf_size`
+/// of them. Triangles index `vertices`.
+///
+/// The top-level BVH over the primitives in world space and the instances comes first,
+/// rooted at the first node. The BVH of every object follows, and the instances of an
+/// object point at its root.
+#[allow(clippy::too_many_arguments)]
+pub fn create_instanced_bvh(
+    primitives: &mut [EnumPrimitivePod],
+    objects: &[Range<usize>],
+    instances: &mut [InstancePod],
+    vertices: &[VertexPod],
+    time0: f32,
+    time1: f32,
+    builder: BVHBuilder,
+    max_leaf_size: usize,
+    rng: &mut impl Rng,
+) -> Result<Vec<BVHNodePod>, StackOverflow> {
+    let max_leaf_size = max_leaf_size.max(1);
+    let bounding_box = |p: &EnumPrimitivePod| p.bounding_box(time0, time1, vertices);
 
-    create_bvh_inner(world, time0, time1, 0, world.len(), &mut ret, rng);
+    let mut object_trees = Vec::with_capacity(objects.len());
+    for object in objects {
+        let primitives = &mut primitives[object.clone()];
+        let boxes = primitives.iter().map(bounding_box).collect();
+        object_trees.push(build_tree(
+            primitives,
+            &mut [],
+            boxes,
+            builder,
+            max_leaf_size,
+            rng,
+        )?);
+    }
 
-    ret.into_iter().map(Into::into).collect()
+    let world_len = objects
+        .first()
+        .map_or(primitives.len(), |object| object.start);
+    let world = &mut primitives[..world_len];
+    let mut boxes: Vec<AABB> = world.iter().map(bounding_box).collect();
+    boxes.extend(instances.iter().map(|instance| {
+        instance.bounding_box(&object_trees[instance.object() as usize][0].aabb())
+    }));
+    let mut nodes = build_tree(world, instances, boxes, builder, max_leaf_size, rng)?;
+
+    let mut roots = Vec::with_capacity(objects.len());
+    for (object, tree) in objects.iter().zip(object_trees) {
+        let offset = nodes.len() as u32;
+        roots.push(offset);
+        nodes.extend(
+            tree.into_iter()
+                .map(|node| node.offset(offset, object.start as u32)),
+        );
+    }
+    for instance in instances {
+        instance.set_root(roots[instance.object() as usize]);
+    }
+
+    Ok(nodes)
+}
+
+/// Builds one level of the BVH over `primitives` followed by `instances`, whose bounding
+/// boxes are `boxes` in that order
+fn build_tree(
+    primitives: &mut [EnumPrimitivePod],
+    instances: &mut [InstancePod],
+    boxes: Vec<AABB>,
+    builder: BVHBuilder,
+    max_leaf_size: usize,
+    rng: &mut impl Rng,
+) -> Result<Vec<BVHNodePod>, StackOverflow> {
+    let mut bounds = Primitives::new(boxes, primitives.len());
+
+    let mut b = Builder {
+        max_leaf_size,
+        out: Vec::new(),
+    };
+    b.build(builder, &mut bounds, rng);
+    let positions = bounds.apply(primitives, instances);
+
+    let nodes: Vec<BVHNodePod> = b
+        .out
+        .into_iter()
+        .map(|mut node| {
+            if let BVHChildInner::World { first, .. } | BVHChildInner::Instances { first, .. } =
+                &mut node.child
+            {
+                *first = positions[*first];
+            }
+            node.into()
+        })
+        .collect();
+
+    let required = max_stack_depth(&nodes, 0);
+    if required > STACK_SIZE {
+        return Err(StackOverflow {
+            required,
+            capacity: STACK_SIZE,
+        });
+    }
+
+    Ok(nodes)
+}
+
+/// Most entries `bvh::BVH::hit` keeps on its stack when every box of the tree at `root` is
+/// hit. The trees of instanced objects are traversed with stacks of their own.
+pub fn max_stack_depth(nodes: &[BVHNodePod], root: u32) -> usize {
+    if nodes.is_empty() {
+        return 0;
+    }
+
+    // (node, stack length when the node is popped)
+    let mut todo = vec![(root, 0)];
+    let mut max = 1;
+
+    while let Some((i, stack)) = todo.pop() {
+        let node = &nodes[i as usize];
+        match node.child[0] {
+            0 => todo.push((node.child[1], stack)),
+            1 => {
+                max = max.max(stack + 2);
+                todo.push((node.child[1], stack));
+                todo.push((node.child[2], stack + 1));
+            }
+            2 | 3 => {}
+            kind => unreachable!("unknown BVH node kind {}", kind),
+        }
+    }
+
+    max
+}
+
+impl BVHNodePod {
+    pub(crate) fn minimum(&self) -> Vec3 {
+        vec3(self.minimum[0], self.minimum[1], self.minimum[2])
+    }
+
+    pub(crate) fn maximum(&self) -> Vec3 {
+        vec3(self.maximum[0], self.maximum[1], self.maximum[2])
+    }
+
+    fn aabb(&self) -> AABB {
+        AABB {
+            minimum: self.minimum(),
+            maximum: self.maximum(),
+        }
+    }
+
+    /// Moves a node of an object BVH to where the object and its nodes start
+    fn offset(mut self, first_node: u32, first_primitive: u32) -> Self {
+        match self.child[0] {
+            0 => self.child[1] += first_node,
+            1 => {
+                self.child[1] += first_node;
+                self.child[2] += first_node;
+            }
+            2 => self.child[3] += first_primitive,
+            kind => unreachable!("object BVHs have no nodes of kind {}", kind),
+        }
+        self
+    }
+
+    pub(crate) fn surface_area(&self) -> f32 {
+        surface_area(&self.aabb())
+    }
+
+    /// Surface area of the intersection of the two boxes
+    pub(crate) fn overlap_area(&self, other: &BVHNodePod) -> f32 {
+        let minimum = self.minimum().max(other.minimum());
+        let maximum = self.maximum().min(other.maximum());
+        if minimum.cmpgt(maximum).any() {
+            return 0.0;
+        }
+        surface_area(&AABB { minimum, maximum })
+    }
+}
+
+/// Expected cost of tracing a ray through a BVH made by `create_instanced_bvh` under the
+/// surface area heuristic, counting one unit per box test and per primitive test
+pub fn sah_cost(nodes: &[BVHNodePod], instances: &[InstancePod]) -> f32 {
+    if nodes.is_empty() {
+        return 0.0;
+    }
+    tree_sah_cost(nodes, 0, instances, &object_sah_costs(nodes, instances))
+}
+
+/// `tree_sah_cost` of the tree of every instanced object, by root
+pub(crate) fn object_sah_costs(
+    nodes: &[BVHNodePod],
+    instances: &[InstancePod],
+) -> BTreeMap<u32, f32> {
+    let mut costs = BTreeMap::new();
+    for instance in instances {
+        costs
+            .entry(instance.root())
+            .or_insert_with(|| tree_sah_cost(nodes, instance.root(), &[], &BTreeMap::new()));
+    }
+    costs
+}
+
+/// SAH cost of the tree at `root`, relative to its root box. A ray hitting an instance leaf
+/// goes through the tree of every instance of the leaf, whose cost in the space of the object
+/// is taken from `object_costs`.
+pub(crate) fn tree_sah_cost(
+    nodes: &[BVHNodePod],
+    root: u32,
+    instances: &[InstancePod],
+    object_costs: &BTreeMap<u32, f32>,
+) -> f32 {
+    let root_area = nodes[root as usize].surface_area();
+    let mut cost = 0.0;
+    let mut todo = vec![root];
+
+    while let Some(i) = todo.pop() {
+        let node = &nodes[i as usize];
+        // Every box of a flat tree is hit
+        let area = if root_area > 0.0 {
+            node.surface_area() / root_area
+        } else {
+            1.0
+        };
+        let tests = match node.child[0] {
+            0 => {
+                todo.push(node.child[1]);
+                1.0
+            }
+            1 => {
+                todo.push(node.child[1]);
+                todo.push(node.child[2]);
+                1.0
+            }
+            // A box test, plus the primitive tests of a leaf
+            2 => (1 + node.child[1]) as f32,
+            // A box test, plus the trees of the instances of a leaf
+            3 => {
+                let (first, count) = (node.child[3] as usize, node.child[1] as usize);
+                1.0 + instances[first..first + count]
+                    .iter()
+                    .map(|instance| object_costs[&instance.root()])
+                    .sum::<f32>()
+            }
+            kind => unreachable!("unknown BVH node kind {}", kind),
+        };
+        cost += tests * area;
+    }
+
+    cost
+}
+
+impl From<BVHNodePod> for BVHNode {
+    fn from(pod: BVHNodePod) -> Self {
+        Self {
+            aabb: AABB {
+                minimum: vec3(pod.minimum[0], pod.minimum[1], pod.minimum[2]),
+                maximum: vec3(pod.maximum[0], pod.maximum[1], pod.maximum[2]),
+            },
+            child: uvec4(pod.child[0], pod.child[1], pod.child[2], pod.child[3]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pod::{EnumMaterialPod, VertexPod};
+    use spirv_std::glam::Mat4;
+
+    fn scene(rng: &mut impl Rng) -> Vec<EnumPrimitivePod> {
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+        // A dense cluster next to a sparse one is where the median split does badly
+        (0..200)
+            .map(|i| {
+                let spread = if i < 150 { 1.0 } else { 100.0 };
+                let center = vec3(
+                    rng.gen_range(-spread..spread),
+                    rng.gen_range(-spread..spread),
+                    rng.gen_range(-spread..spread),
+                );
+                EnumPrimitivePod::new_sphere(center, 0.1, material)
+            })
+            .collect()
+    }
+
+    /// Primitives referenced by the leaves, sorted
+    fn leaves(nodes: &[BVHNodePod]) -> Vec<u32> {
+        let mut leaves: Vec<u32> = nodes
+            .iter()
+            .filter(|node| node.child[0] == 2)
+            .flat_map(|node| node.child[3]..node.child[3] + node.child[1])
+            .collect();
+        leaves.sort_unstable();
+        leaves
+    }
+
+    #[test]
+    fn spheres_and_triangles_share_a_tree() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut world = scene(&mut rng);
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+
+        let vertices: Vec<VertexPod> = (0..30)
+            .map(|_| {
+                VertexPod::without_normal(vec3(
+                    rng.gen_range(-5.0..5.0),
+                    rng.gen_range(-5.0..5.0),
+                    rng.gen_range(-5.0..5.0),
+                ))
+            })
+            .collect();
+        world.extend(
+            (0..10)
+                .map(|i| EnumPrimitivePod::new_triangle([3 * i, 3 * i + 1, 3 * i + 2], material)),
+        );
+        let original = world.clone();
+
+        let bvh = create_bvh(
+            &mut world,
+            &vertices,
+            0.0,
+            1.0,
+            BVHBuilder::Sah,
+            DEFAULT_MAX_LEAF_SIZE,
+            &mut rng,
+        )
+        .unwrap();
+
+        assert_eq!(leaves(&bvh), (0..world.len() as u32).collect::<Vec<_>>());
+        // Primitives are reordered, not rewritten
+        fn sorted(primitives: &[EnumPrimitivePod]) -> Vec<&[u8]> {
+            let mut bytes: Vec<&[u8]> = primitives.iter().map(bytemuck::bytes_of).collect();
+            bytes.sort_unstable();
+            bytes
+        }
+        assert_eq!(sorted(&world), sorted(&original));
+    }
+
+    #[test]
+    fn moving_spheres_are_bounded_over_the_shutter() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+        let sphere = EnumPrimitivePod::new_moving_sphere(
+            Vec3::ZERO,
+            vec3(2.0, 0.0, 0.0),
+            0.0,
+            1.0,
+            0.5,
+            material,
+        );
+
+        for &(time0, time1, min_x, max_x) in &[
+            (0.0, 1.0, -0.5, 2.5),
+            (0.0, 0.5, -0.5, 1.5),
+            (0.25, 0.75, 0.0, 2.0),
+        ] {
+            let bvh = create_bvh(
+                &mut [sphere],
+                &[],
+                time0,
+                time1,
+                BVHBuilder::Sah,
+                1,
+                &mut rng,
+            )
+            .unwrap();
+            assert_eq!(bvh[0].minimum(), vec3(min_x, -0.5, -0.5));
+            assert_eq!(bvh[0].maximum(), vec3(max_x, 0.5, 0.5));
+        }
+    }
+
+    #[test]
+    fn flat_primitives_get_padded_boxes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+
+        for primitive in &[
+            EnumPrimitivePod::new_quad(
+                vec3(0.0, 1.0, 0.0),
+                vec3(2.0, 0.0, 0.0),
+                vec3(0.0, 0.0, 3.0),
+                material,
+            ),
+            EnumPrimitivePod::new_rect(1, vec3(0.0, 1.0, 0.0), vec3(2.0, 1.0, 3.0), material),
+        ] {
+            let bvh = create_bvh(
+                &mut [*primitive],
+                &[],
+                0.0,
+                1.0,
+                BVHBuilder::Sah,
+                1,
+                &mut rng,
+            )
+            .unwrap();
+            // Only the flat axis is widened
+            assert_eq!(bvh[0].minimum(), vec3(0.0, 1.0 - 0.5e-4, 0.0));
+            assert_eq!(bvh[0].maximum(), vec3(2.0, 1.0 + 0.5e-4, 3.0));
+        }
+    }
+
+    #[test]
+    fn media_are_bounded_by_their_boundary() {
+        let material = EnumMaterialPod::new_isotropic(vec3(0.5, 0.5, 0.5));
+        let sphere =
+            EnumPrimitivePod::new_constant_medium_sphere(vec3(1.0, 2.0, 3.0), 0.5, 0.1, material)
+                .bounding_box(0.0, 1.0, &[]);
+        assert_eq!(sphere.minimum, vec3(0.5, 1.5, 2.5));
+        assert_eq!(sphere.maximum, vec3(1.5, 2.5, 3.5));
+
+        let cube = EnumPrimitivePod::new_constant_medium_box(
+            vec3(1.0, 2.0, 3.0),
+            vec3(-1.0, 0.0, 0.0),
+            0.1,
+            material,
+        )
+        .bounding_box(0.0, 1.0, &[]);
+        assert_eq!(cube.minimum, vec3(-1.0, 0.0, 0.0));
+        assert_eq!(cube.maximum, vec3(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn boxes_are_closed() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+        let mut faces =
+            EnumPrimitivePod::new_box(vec3(1.0, 2.0, 3.0), vec3(-1.0, 0.0, 0.0), material);
+
+        let bvh = create_bvh(&mut faces, &[], 0.0, 1.0, BVHBuilder::Sah, 1, &mut rng).unwrap();
+        assert_eq!(leaves(&bvh), (0..6).collect::<Vec<_>>());
+        assert_eq!(
+            bvh[0].minimum(),
+            vec3(-1.0, 0.0, 0.0) - vec3(0.5e-4, 0.5e-4, 0.5e-4)
+        );
+        assert_eq!(
+            bvh[0].maximum(),
+            vec3(1.0, 2.0, 3.0) + vec3(0.5e-4, 0.5e-4, 0.5e-4)
+        );
+    }
+
+    /// Kind and range of the leaves reachable from `root`, sorted
+    fn reachable_leaves(nodes: &[BVHNodePod], root: u32) -> Vec<(u32, Range<u32>)> {
+        let mut leaves = Vec::new();
+        let mut todo = vec![root];
+        while let Some(i) = todo.pop() {
+            let child = nodes[i as usize].child;
+            match child[0] {
+                0 => todo.push(child[1]),
+                1 => todo.extend_from_slice(&child[1..3]),
+                kind => leaves.push((kind, child[3]..child[3] + child[1])),
+            }
+        }
+        leaves.sort_by_key(|(kind, range)| (*kind, range.start));
+        leaves
+    }
+
+    /// Ranges of the given kind, which must tile `0..end`
+    fn assert_tiles(leaves: &[(u32, Range<u32>)], kind: u32, end: u32) {
+        let mut next = 0;
+        for (_, range) in leaves.iter().filter(|(k, _)| *k == kind) {
+            assert_eq!(range.start, next);
+            assert!(!range.is_empty());
+            next = range.end;
+        }
+        assert_eq!(next, end);
+    }
+
+    #[test]
+    fn instances_point_at_their_object_trees() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+        // 3 spheres in world space, then objects of 10 and 5 spheres
+        let mut primitives: Vec<EnumPrimitivePod> = (0..18)
+            .map(|i| EnumPrimitivePod::new_sphere(vec3(i as f32, 0.0, 0.0), 0.4, material))
+            .collect();
+        let objects = [3..13, 13..18];
+        let mut instances: Vec<InstancePod> = (0..20)
+            .map(|i| {
+                let translation = vec3(rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0), 0.0);
+                InstancePod::new(Mat4::from_translation(translation), i % 2, None)
+            })
+            .collect();
+        let objects_before: Vec<u32> = instances.iter().map(InstancePod::object).collect();
+        let bounds: Vec<AABB> = instances
+            .iter()
+            .zip(&objects_before)
+            .map(|(instance, &object)| {
+                let range = objects[object as usize].clone();
+                let aabb = primitives[range]
+                    .iter()
+                    .map(|p| p.bounding_box(0.0, 1.0, &[]))
+                    .reduce(surrounding_box)
+                    .unwrap();
+                instance.bounding_box(&aabb)
+            })
+            .collect();
+
+        let bvh = create_instanced_bvh(
+            &mut primitives,
+            &objects,
+            &mut instances,
+            &[],
+            0.0,
+            1.0,
+            BVHBuilder::Sah,
+            2,
+            &mut rng,
+        )
+        .unwrap();
+
+        let top = reachable_leaves(&bvh, 0);
+        assert!(top.iter().all(|(kind, _)| *kind == 2 || *kind == 3));
+        assert_tiles(&top, 2, 3);
+        assert_tiles(&top, 3, 20);
+
+        let root = bvh[0].aabb();
+        for aabb in &bounds {
+            assert!(root.minimum.cmple(aabb.minimum).all());
+            assert!(root.maximum.cmpge(aabb.maximum).all());
+        }
+
+        // Building reorders the instances but leaves their objects alone
+        let mut objects_after: Vec<u32> = instances.iter().map(InstancePod::object).collect();
+        objects_after.sort_unstable();
+        let mut objects_before = objects_before;
+        objects_before.sort_unstable();
+        assert_eq!(objects_after, objects_before);
+
+        for instance in &instances {
+            let leaves = reachable_leaves(&bvh, instance.root());
+            assert!(leaves.iter().all(|(kind, _)| *kind == 2));
+            let first = leaves[0].1.start;
+            let object = &objects[instance.object() as usize];
+            assert!(leaves
+                .iter()
+                .all(|(_, r)| object.contains(&(r.start as usize))));
+            let shifted: Vec<(u32, Range<u32>)> = leaves
+                .into_iter()
+                .map(|(kind, r)| (kind, r.start - first..r.end - first))
+                .collect();
+            assert_tiles(&shifted, 2, object.len() as u32);
+        }
+    }
+
+    #[test]
+    fn every_primitive_is_in_one_leaf() {
+        for &builder in &[BVHBuilder::Sah, BVHBuilder::RandomMedian] {
+            for &max_leaf_size in &[1, 4, 16] {
+                let mut rng = StdRng::seed_from_u64(0);
+                let mut world = scene(&mut rng);
+                let bvh = create_bvh(&mut world, &[], 0.0, 1.0, builder, max_leaf_size, &mut rng)
+                    .unwrap();
+                assert_eq!(leaves(&bvh), (0..world.len() as u32).collect::<Vec<_>>());
+                assert!(bvh
+                    .iter()
+                    .filter(|node| node.child[0] == 2)
+                    .all(|node| (1..=max_leaf_size as u32).contains(&node.child[1])));
+            }
+        }
+    }
+
+    #[test]
+    fn larger_leaves_make_smaller_trees() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let world = scene(&mut rng);
+
+        let single = create_bvh(
+            &mut world.clone(),
+            &[],
+            0.0,
+            1.0,
+            BVHBuilder::Sah,
+            1,
+            &mut rng,
+        );
+        let multi = create_bvh(
+            &mut world.clone(),
+            &[],
+            0.0,
+            1.0,
+            BVHBuilder::Sah,
+            DEFAULT_MAX_LEAF_SIZE,
+            &mut rng,
+        );
+        assert_eq!(single.unwrap().len(), 2 * world.len() - 1);
+        assert!(multi.unwrap().len() < world.len());
+    }
+
+    #[test]
+    fn sah_is_cheaper_than_random_median() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let world = scene(&mut rng);
+
+        let sah = create_bvh(
+            &mut world.clone(),
+            &[],
+            0.0,
+            1.0,
+            BVHBuilder::Sah,
+            1,
+            &mut rng,
+        )
+        .unwrap();
+        let median = create_bvh(
+            &mut world.clone(),
+            &[],
+            0.0,
+            1.0,
+            BVHBuilder::RandomMedian,
+            1,
+            &mut rng,
+        )
+        .unwrap();
+        assert!(sah_cost(&sah, &[]) < sah_cost(&median, &[]));
+    }
+
+    #[test]
+    fn coincident_primitives_still_split() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+        let mut world = vec![EnumPrimitivePod::new_sphere(Vec3::ZERO, 1.0, material); 5];
+        let bvh = create_bvh(&mut world, &[], 0.0, 1.0, BVHBuilder::Sah, 1, &mut rng).unwrap();
+        assert_eq!(leaves(&bvh).len(), 5);
+    }
+
+    #[test]
+    fn stack_is_logarithmic() {
+        for &builder in &[BVHBuilder::Sah, BVHBuilder::RandomMedian] {
+            let mut rng = StdRng::seed_from_u64(0);
+            let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+            // Exponentially spaced spheres make the SAH tree a long chain whose small
+            // branches are on the left
+            let mut world: Vec<EnumPrimitivePod> = (0..4000)
+                .map(|i| {
+                    EnumPrimitivePod::new_sphere(vec3(-(1.01f32.powi(i)), 0.0, 0.0), 0.01, material)
+                })
+                .collect();
+            let bvh = create_bvh(&mut world, &[], 0.0, 1.0, builder, 1, &mut rng).unwrap();
+            // log2(4000) < 12
+            assert!(max_stack_depth(&bvh, 0) <= 13, "{:?}", builder);
+        }
+    }
 }
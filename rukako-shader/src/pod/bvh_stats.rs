@@ -0,0 +1,447 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, io,
+};
+
+use spirv_std::glam::{vec3, Mat4};
+
+use super::{
+    bvh::{max_stack_depth, object_sah_costs, tree_sah_cost, BVHNodePod},
+    InstancePod,
+};
+
+/// Quality metrics of one tree of a BVH made by `create_instanced_bvh`: the top-level tree,
+/// or the tree of an object in the space of the object
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BVHStats {
+    pub nodes: usize,
+    /// Leaves holding primitives
+    pub leaves: usize,
+    /// Leaves holding instances, only found in the top-level tree
+    pub instance_leaves: usize,
+    /// Most primitives or instances referenced by one leaf
+    pub max_leaf_size: usize,
+    /// Nodes on the longest path from the root to a leaf, both included
+    pub max_depth: usize,
+    /// Most entries `bvh::BVH::hit` keeps on its stack when every box is hit
+    pub max_stack: usize,
+    /// Including the trees of the instances the tree reaches, see `bvh::sah_cost`
+    pub sah_cost: f32,
+    /// Total surface area of sibling overlaps relative to the root
+    pub overlap: f32,
+    /// Largest overlap of two siblings relative to their parent
+    pub max_overlap: f32,
+}
+
+/// `BVHStats` of the top-level tree and of the tree of every instanced object
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InstancedBVHStats {
+    pub top: BVHStats,
+    pub objects: Vec<ObjectBVHStats>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ObjectBVHStats {
+    /// Index of the root node of the tree
+    pub root: u32,
+    /// Number of instances of the object
+    pub instances: usize,
+    pub stats: BVHStats,
+}
+
+impl BVHStats {
+    /// Statistics of the tree at `root`, whose instance leaves index `instances`
+    pub fn new(nodes: &[BVHNodePod], root: u32, instances: &[InstancePod]) -> Self {
+        let mut stats = BVHStats::default();
+        if nodes.is_empty() {
+            return stats;
+        }
+        stats.max_stack = max_stack_depth(nodes, root);
+        stats.sah_cost = tree_sah_cost(nodes, root, instances, &object_sah_costs(nodes, instances));
+
+        let root_area = nodes[root as usize].surface_area();
+        let mut todo = vec![(root, 1)];
+
+        while let Some((i, depth)) = todo.pop() {
+            let node = &nodes[i as usize];
+            stats.nodes += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+
+            match node.child[0] {
+                0 => todo.push((node.child[1], depth + 1)),
+                1 => {
+                    let (left, right) = (
+                        &nodes[node.child[1] as usize],
+                        &nodes[node.child[2] as usize],
+                    );
+
+                    let overlap = left.overlap_area(right);
+                    if root_area > 0.0 {
+                        stats.overlap += overlap / root_area;
+                    }
+                    if node.surface_area() > 0.0 {
+                        stats.max_overlap = stats.max_overlap.max(overlap / node.surface_area());
+                    }
+
+                    todo.push((node.child[1], depth + 1));
+                    todo.push((node.child[2], depth + 1));
+                }
+                2 => {
+                    stats.leaves += 1;
+                    stats.max_leaf_size = stats.max_leaf_size.max(node.child[1] as usize);
+                }
+                3 => {
+                    stats.instance_leaves += 1;
+                    stats.max_leaf_size = stats.max_leaf_size.max(node.child[1] as usize);
+                }
+                kind => unreachable!("unknown BVH node kind {}", kind),
+            }
+        }
+
+        stats
+    }
+}
+
+impl InstancedBVHStats {
+    pub fn new(nodes: &[BVHNodePod], instances: &[InstancePod]) -> Self {
+        let mut counts = BTreeMap::new();
+        for instance in instances {
+            *counts.entry(instance.root()).or_insert(0) += 1;
+        }
+
+        Self {
+            top: BVHStats::new(nodes, 0, instances),
+            objects: counts
+                .into_iter()
+                .map(|(root, instances)| ObjectBVHStats {
+                    root,
+                    instances,
+                    stats: BVHStats::new(nodes, root, &[]),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "nodes:       {}", self.nodes)?;
+        writeln!(f, "leaves:      {}", self.leaves)?;
+        if self.instance_leaves > 0 {
+            writeln!(f, "inst leaves: {}", self.instance_leaves)?;
+        }
+        writeln!(f, "max leaf:    {}", self.max_leaf_size)?;
+        writeln!(f, "max depth:   {}", self.max_depth)?;
+        writeln!(f, "max stack:   {}", self.max_stack)?;
+        writeln!(f, "SAH cost:    {:.3}", self.sah_cost)?;
+        writeln!(f, "overlap:     {:.3}", self.overlap)?;
+        write!(f, "max overlap: {:.3}", self.max_overlap)
+    }
+}
+
+impl fmt::Display for InstancedBVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.objects.is_empty() {
+            return write!(f, "{}", self.top);
+        }
+
+        write!(f, "top level:\n{}", self.top)?;
+        for object in &self.objects {
+            write!(
+                f,
+                "\nobject at node {} with {} instances:\n{}",
+                object.root, object.instances, object.stats
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a box, mapped by `transform`, as a wireframe object
+fn write_box(
+    node: &BVHNodePod,
+    name: &str,
+    transform: Mat4,
+    vertex_count: &mut usize,
+    w: &mut impl io::Write,
+) -> io::Result<()> {
+    let (min, max) = (node.minimum(), node.maximum());
+    writeln!(w, "o {}", name)?;
+    for corner in 0..8 {
+        let pick = |axis: usize| {
+            if corner & (1 << axis) == 0 {
+                min[axis]
+            } else {
+                max[axis]
+            }
+        };
+        let p = transform.transform_point3(vec3(pick(0), pick(1), pick(2)));
+        writeln!(w, "v {} {} {}", p.x, p.y, p.z)?;
+    }
+
+    // Corners differing in exactly one axis share an edge
+    let base = *vertex_count + 1;
+    for corner in 0..8 {
+        for axis in 0..3 {
+            let other = corner | (1 << axis);
+            if other != corner {
+                writeln!(w, "l {} {}", base + corner, base + other)?;
+            }
+        }
+    }
+    *vertex_count += 8;
+    Ok(())
+}
+
+/// Writes every box of the top-level tree as a wireframe object named after its node index,
+/// and the boxes of the tree of every instance as placed in the world by the instance
+pub fn write_obj(
+    nodes: &[BVHNodePod],
+    instances: &[InstancePod],
+    mut w: impl io::Write,
+) -> io::Result<()> {
+    let mut vertex_count = 0;
+    if !nodes.is_empty() {
+        write_tree(
+            nodes,
+            0,
+            instances,
+            Mat4::IDENTITY,
+            "",
+            &mut vertex_count,
+            &mut w,
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the boxes of the tree at `root` mapped by `transform`, with names starting with
+/// `prefix`
+fn write_tree(
+    nodes: &[BVHNodePod],
+    root: u32,
+    instances: &[InstancePod],
+    transform: Mat4,
+    prefix: &str,
+    vertex_count: &mut usize,
+    w: &mut impl io::Write,
+) -> io::Result<()> {
+    let mut todo = vec![root];
+    while let Some(i) = todo.pop() {
+        let node = &nodes[i as usize];
+        let name = format!("{}node{}", prefix, i);
+        write_box(node, &name, transform, vertex_count, w)?;
+
+        match node.child[0] {
+            0 => todo.push(node.child[1]),
+            1 => {
+                todo.push(node.child[2]);
+                todo.push(node.child[1]);
+            }
+            2 => {}
+            3 => {
+                let (first, count) = (node.child[3] as usize, node.child[1] as usize);
+                for (k, instance) in instances.iter().enumerate().skip(first).take(count) {
+                    // Object trees hold no instances
+                    write_tree(
+                        nodes,
+                        instance.root(),
+                        &[],
+                        instance.object_to_world(),
+                        &format!("instance{}_", k),
+                        vertex_count,
+                        w,
+                    )?;
+                }
+            }
+            kind => unreachable!("unknown BVH node kind {}", kind),
+        }
+    }
+    Ok(())
+}
+
+/// Writes the tree in Graphviz dot format. Instance leaves point at the roots of the trees of
+/// their objects.
+pub fn write_dot(
+    nodes: &[BVHNodePod],
+    instances: &[InstancePod],
+    mut w: impl io::Write,
+) -> io::Result<()> {
+    writeln!(w, "digraph bvh {{")?;
+    writeln!(w, "    node [shape=box, fontname=monospace];")?;
+    for (i, node) in nodes.iter().enumerate() {
+        match node.child[0] {
+            0 => writeln!(w, "    n{} -> n{};", i, node.child[1])?,
+            1 => {
+                writeln!(w, "    n{} -> n{};", i, node.child[1])?;
+                writeln!(w, "    n{} -> n{};", i, node.child[2])?;
+            }
+            2 => writeln!(
+                w,
+                "    n{} [label=\"{}: primitives {}..{}\", style=filled];",
+                i,
+                i,
+                node.child[3],
+                node.child[3] + node.child[1]
+            )?,
+            3 => {
+                let (first, count) = (node.child[3], node.child[1]);
+                writeln!(
+                    w,
+                    "    n{} [label=\"{}: instances {}..{}\", style=filled];",
+                    i,
+                    i,
+                    first,
+                    first + count
+                )?;
+                let roots: BTreeSet<u32> = instances[first as usize..(first + count) as usize]
+                    .iter()
+                    .map(InstancePod::root)
+                    .collect();
+                for root in roots {
+                    writeln!(w, "    n{} -> n{} [style=dashed];", i, root)?;
+                }
+            }
+            kind => unreachable!("unknown BVH node kind {}", kind),
+        }
+    }
+    writeln!(w, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::pod::{
+        bvh::{create_bvh, create_instanced_bvh, sah_cost, BVHBuilder},
+        EnumMaterialPod, EnumPrimitivePod,
+    };
+
+    fn line(n: usize) -> Vec<BVHNodePod> {
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+        let mut world: Vec<EnumPrimitivePod> = (0..n)
+            .map(|i| EnumPrimitivePod::new_sphere(vec3(i as f32, 0.0, 0.0), 0.25, material))
+            .collect();
+        let mut rng = StdRng::seed_from_u64(0);
+        create_bvh(&mut world, &[], 0.0, 1.0, BVHBuilder::Sah, 1, &mut rng).unwrap()
+    }
+
+    #[test]
+    fn balanced_tree() {
+        let stats = BVHStats::new(&line(8), 0, &[]);
+        assert_eq!(stats.nodes, 15);
+        assert_eq!(stats.leaves, 8);
+        assert_eq!(stats.max_leaf_size, 1);
+        assert_eq!(stats.max_depth, 4);
+        // Disjoint spheres on a line never overlap
+        assert_eq!(stats.overlap, 0.0);
+        assert!(stats.max_stack <= stats.max_depth + 1);
+    }
+
+    #[test]
+    fn single_leaf() {
+        let stats = BVHStats::new(&line(1), 0, &[]);
+        assert_eq!(stats.nodes, 1);
+        assert_eq!(stats.leaves, 1);
+        assert_eq!(stats.max_depth, 1);
+        assert_eq!(stats.max_stack, 1);
+    }
+
+    #[test]
+    fn exports() {
+        let nodes = line(4);
+
+        let mut obj = Vec::new();
+        write_obj(&nodes, &[], &mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 8 * 7);
+        assert_eq!(obj.lines().filter(|l| l.starts_with("l ")).count(), 12 * 7);
+
+        let mut dot = Vec::new();
+        write_dot(&nodes, &[], &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert_eq!(dot.matches("->").count(), 6);
+    }
+
+    /// `world` spheres in world space and `n_instances` instances of an object of 4 spheres on
+    /// a line, 10 apart along y
+    fn instanced(world: usize, n_instances: usize) -> (Vec<BVHNodePod>, Vec<InstancePod>) {
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+        let mut primitives: Vec<EnumPrimitivePod> = (0..world + 4)
+            .map(|i| EnumPrimitivePod::new_sphere(vec3(i as f32, 0.0, 0.0), 0.25, material))
+            .collect();
+        let mut instances: Vec<InstancePod> = (0..n_instances)
+            .map(|i| {
+                let translation = vec3(0.0, 10.0 * (i + 1) as f32, 0.0);
+                InstancePod::new(Mat4::from_translation(translation), 0, None)
+            })
+            .collect();
+        let mut rng = StdRng::seed_from_u64(0);
+        let bvh = create_instanced_bvh(
+            &mut primitives,
+            std::slice::from_ref(&(world..world + 4)),
+            &mut instances,
+            &[],
+            0.0,
+            1.0,
+            BVHBuilder::Sah,
+            1,
+            &mut rng,
+        )
+        .unwrap();
+        (bvh, instances)
+    }
+
+    #[test]
+    fn object_trees_are_reported_apart() {
+        let (bvh, instances) = instanced(1, 3);
+        let stats = InstancedBVHStats::new(&bvh, &instances);
+
+        assert_eq!(stats.top.leaves, 1);
+        assert_eq!(stats.top.instance_leaves, 3);
+        assert_eq!(stats.top.nodes, 7);
+        assert_eq!(stats.top.sah_cost, sah_cost(&bvh, &instances));
+
+        assert_eq!(stats.objects.len(), 1);
+        let object = &stats.objects[0];
+        assert_eq!(object.instances, 3);
+        assert_eq!(object.stats, BVHStats::new(&line(4), 0, &[]));
+        assert_eq!(stats.top.nodes + object.stats.nodes, bvh.len());
+    }
+
+    #[test]
+    fn instance_leaves_cost_their_object_trees() {
+        // The top-level tree is a single leaf holding the instance
+        let (bvh, instances) = instanced(0, 1);
+        let stats = InstancedBVHStats::new(&bvh, &instances);
+        let expected = 1.0 + stats.objects[0].stats.sah_cost;
+        assert!((sah_cost(&bvh, &instances) - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn instances_are_exported_in_world_space() {
+        let (bvh, instances) = instanced(1, 3);
+
+        let mut obj = Vec::new();
+        write_obj(&bvh, &instances, &mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert_eq!(
+            obj.lines().filter(|l| l.starts_with("o ")).count(),
+            7 + 3 * 7
+        );
+        // The boxes of the last instance are moved 30 up
+        let y = |l: &str| l.split(' ').nth(2).unwrap().parse::<f32>().unwrap();
+        let max_y = obj
+            .lines()
+            .filter(|l| l.starts_with("v "))
+            .map(y)
+            .fold(f32::NEG_INFINITY, f32::max);
+        assert!((max_y - 30.25).abs() < 1e-3, "{}", max_y);
+
+        let mut dot = Vec::new();
+        write_dot(&bvh, &instances, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert_eq!(dot.matches("style=dashed").count(), 3);
+    }
+}
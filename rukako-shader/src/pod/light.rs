@@ -0,0 +1,50 @@
+use std::ops::Range;
+
+use super::EnumPrimitivePod;
+
+/// Indices of the emissive primitives in world space, the lights next event estimation
+/// samples. `primitives` and `objects` are laid out as for `bvh::create_instanced_bvh`,
+/// which must have reordered them already. Emissive primitives of objects only contribute
+/// when paths hit them.
+pub fn light_list(primitives: &[EnumPrimitivePod], objects: &[Range<usize>]) -> Vec<u32> {
+    let world_len = objects
+        .first()
+        .map_or(primitives.len(), |object| object.start);
+    primitives[..world_len]
+        .iter()
+        .enumerate()
+        .filter(|(_, primitive)| primitive.is_light())
+        .map(|(i, _)| i as u32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use spirv_std::glam::vec3;
+
+    use super::*;
+    use crate::pod::EnumMaterialPod;
+
+    #[test]
+    fn lights_are_emissive_primitives_in_world_space() {
+        let light = EnumMaterialPod::new_diffuse_light(vec3(4.0, 4.0, 4.0));
+        let white = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+        let primitives = [
+            EnumPrimitivePod::new_sphere(vec3(0.0, 0.0, 0.0), 1.0, white),
+            EnumPrimitivePod::new_quad(
+                vec3(0.0, 2.0, 0.0),
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 0.0, 1.0),
+                light,
+            ),
+            EnumPrimitivePod::new_constant_medium_sphere(vec3(3.0, 0.0, 0.0), 1.0, 0.5, light),
+            EnumPrimitivePod::new_sphere(vec3(0.0, 5.0, 0.0), 0.5, light),
+            // Two objects
+            EnumPrimitivePod::new_sphere(vec3(0.0, 0.0, 0.0), 0.5, light),
+            EnumPrimitivePod::new_sphere(vec3(0.0, 0.0, 0.0), 0.5, white),
+        ];
+
+        assert_eq!(light_list(&primitives, &[4..5, 5..6]), vec![1, 3]);
+        assert_eq!(light_list(&primitives, &[]), vec![1, 3, 4]);
+    }
+}
@@ -1,55 +1,343 @@
 use bytemuck::{Pod, Zeroable};
-use spirv_std::glam::{vec3, Vec3};
+use spirv_std::glam::{uvec4, vec3, vec4, Mat4, Vec3};
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
 
-use crate::aabb::AABB;
+use crate::{
+    aabb::AABB, background::EnumBackground, camera::Camera, instance::Instance,
+    material::EnumMaterial, primitive::EnumPrimitive, texture::EnumTexture, triangle::Vertex,
+};
 
 #[cfg(not(target_arch = "spirv"))]
 pub mod bvh;
+#[cfg(not(target_arch = "spirv"))]
+pub mod bvh_stats;
+#[cfg(not(target_arch = "spirv"))]
+pub mod light;
+#[cfg(not(target_arch = "spirv"))]
+pub mod perlin;
 
+/// See `primitive::EnumPrimitive` for the meaning of the fields of each kind
 #[derive(Clone, Copy, Zeroable, Pod)]
 #[repr(C)]
-pub struct SpherePod {
-    center: [f32; 3],
-    _pad0: f32,
-    radius: f32,
-    _pad1: [f32; 3],
+pub struct EnumPrimitivePod {
+    data: [[f32; 4]; 3],
+    indices: [u32; 4],
+    material: EnumMaterialPod,
+    t: u32,
+    _pad: [u32; 3],
+}
+/// See `instance::Instance`
+#[derive(Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct InstancePod {
+    rows: [[f32; 4]; 3],
+    indices: [u32; 4],
     material: EnumMaterialPod,
 }
+
+#[derive(Clone, Copy, Default, Zeroable, Pod)]
+#[repr(C)]
+pub struct VertexPod {
+    position: [f32; 4],
+    normal: [f32; 4],
+}
+
 #[derive(Clone, Copy, Default, Zeroable, Pod)]
 #[repr(C)]
 pub struct EnumMaterialPod {
     data: [f32; 4],
     t: u32,
-    _pad: [f32; 3],
+    texture: u32,
+    _pad: [f32; 2],
 }
 
-impl SpherePod {
-    pub fn new(center: Vec3, radius: f32, material: EnumMaterialPod) -> Self {
+/// See `texture::EnumTexture` for the meaning of the fields of each kind
+#[derive(Clone, Copy, Default, Zeroable, Pod)]
+#[repr(C)]
+pub struct TexturePod {
+    data: [[f32; 4]; 2],
+    indices: [u32; 4],
+    t: u32,
+    _pad: [u32; 3],
+}
+
+#[derive(Clone, Copy, Default, Zeroable, Pod)]
+#[repr(C)]
+pub struct BackgroundPod {
+    data0: [f32; 4],
+    data1: [f32; 4],
+    t: u32,
+    width: u32,
+    height: u32,
+    _pad: u32,
+}
+
+#[derive(Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct CameraPod {
+    origin: [f32; 3],
+    _pad0: f32,
+    lower_left_corner: [f32; 3],
+    _pad1: f32,
+    horizontal: [f32; 3],
+    _pad2: f32,
+    vertical: [f32; 3],
+    _pad3: f32,
+    u: [f32; 3],
+    _pad4: f32,
+    v: [f32; 3],
+    _pad5: f32,
+    lens_radius: f32,
+    time0: f32,
+    time1: f32,
+    _pad6: f32,
+}
+
+impl EnumPrimitivePod {
+    pub fn new_sphere(center: Vec3, radius: f32, material: EnumMaterialPod) -> Self {
         Self {
-            center: [center.x, center.y, center.z],
-            _pad0: 0.0,
-            radius,
-            _pad1: [0.0, 0.0, 0.0],
+            data: [[center.x, center.y, center.z, radius], [0.0; 4], [0.0; 4]],
+            indices: [0; 4],
+            material,
+            t: 0,
+            _pad: [0; 3],
+        }
+    }
+
+    /// `indices` point into the vertex buffer of the scene
+    pub fn new_triangle(indices: [u32; 3], material: EnumMaterialPod) -> Self {
+        Self {
+            data: [[0.0; 4]; 3],
+            indices: [indices[0], indices[1], indices[2], 0],
+            material,
+            t: 1,
+            _pad: [0; 3],
+        }
+    }
+
+    /// Moves linearly from `center0` at `time0` to `center1` at `time1`. `time1` must be
+    /// greater than `time0`
+    pub fn new_moving_sphere(
+        center0: Vec3,
+        center1: Vec3,
+        time0: f32,
+        time1: f32,
+        radius: f32,
+        material: EnumMaterialPod,
+    ) -> Self {
+        Self {
+            data: [
+                [center0.x, center0.y, center0.z, radius],
+                [center1.x, center1.y, center1.z, 0.0],
+                [time0, time1, 0.0, 0.0],
+            ],
+            indices: [0; 4],
+            material,
+            t: 2,
+            _pad: [0; 3],
+        }
+    }
+
+    /// A parallelogram with a corner at `q` and sides `u` and `v`, facing `u × v`
+    pub fn new_quad(q: Vec3, u: Vec3, v: Vec3, material: EnumMaterialPod) -> Self {
+        Self {
+            data: [
+                [q.x, q.y, q.z, 0.0],
+                [u.x, u.y, u.z, 0.0],
+                [v.x, v.y, v.z, 0.0],
+            ],
+            indices: [0; 4],
             material,
+            t: 3,
+            _pad: [0; 3],
         }
     }
 
-    pub fn center(&self) -> Vec3 {
-        vec3(self.center[0], self.center[1], self.center[2])
+    /// A rectangle from `minimum` to `maximum` perpendicular to `axis`, 0 for x, 1 for y and 2
+    /// for z. The corners must share their component along `axis`
+    pub fn new_rect(axis: u32, minimum: Vec3, maximum: Vec3, material: EnumMaterialPod) -> Self {
+        Self {
+            data: [
+                [minimum.x, minimum.y, minimum.z, 0.0],
+                [maximum.x, maximum.y, maximum.z, 0.0],
+                [0.0; 4],
+            ],
+            indices: [axis, 0, 0, 0],
+            material,
+            t: 4,
+            _pad: [0; 3],
+        }
     }
 
-    pub fn radius(&self) -> f32 {
-        self.radius
+    /// The six quads of the box from `minimum` to `maximum`, all facing outwards
+    pub fn new_box(minimum: Vec3, maximum: Vec3, material: EnumMaterialPod) -> [Self; 6] {
+        let (a, b) = (minimum.min(maximum), minimum.max(maximum));
+        let dx = vec3(b.x - a.x, 0.0, 0.0);
+        let dy = vec3(0.0, b.y - a.y, 0.0);
+        let dz = vec3(0.0, 0.0, b.z - a.z);
+
+        [
+            Self::new_quad(vec3(a.x, a.y, b.z), dx, dy, material), // front
+            Self::new_quad(vec3(b.x, a.y, b.z), -dz, dy, material), // right
+            Self::new_quad(vec3(b.x, a.y, a.z), -dx, dy, material), // back
+            Self::new_quad(vec3(a.x, a.y, a.z), dz, dy, material), // left
+            Self::new_quad(vec3(a.x, b.y, b.z), dx, -dz, material), // top
+            Self::new_quad(vec3(a.x, a.y, a.z), dx, dz, material), // bottom
+        ]
     }
 
-    pub fn bounding_box(&self, _time0: f32, _time1: f32) -> AABB {
-        AABB {
-            minimum: self.center() - vec3(self.radius, self.radius, self.radius),
-            maximum: self.center() + vec3(self.radius, self.radius, self.radius),
+    /// A volume of constant `density` filling the sphere of `center` and `radius`. Rays
+    /// scatter inside by `material`, usually `EnumMaterialPod::new_isotropic`
+    pub fn new_constant_medium_sphere(
+        center: Vec3,
+        radius: f32,
+        density: f32,
+        material: EnumMaterialPod,
+    ) -> Self {
+        Self {
+            data: [
+                [center.x, center.y, center.z, radius],
+                [0.0; 4],
+                [-1.0 / density, 0.0, 0.0, 0.0],
+            ],
+            indices: [0; 4],
+            material,
+            t: 5,
+            _pad: [0; 3],
         }
     }
+
+    /// A volume of constant `density` filling the box from `minimum` to `maximum`
+    pub fn new_constant_medium_box(
+        minimum: Vec3,
+        maximum: Vec3,
+        density: f32,
+        material: EnumMaterialPod,
+    ) -> Self {
+        let (a, b) = (minimum.min(maximum), minimum.max(maximum));
+        Self {
+            data: [
+                [a.x, a.y, a.z, 0.0],
+                [b.x, b.y, b.z, 0.0],
+                [-1.0 / density, 0.0, 0.0, 0.0],
+            ],
+            indices: [1, 0, 0, 0],
+            material,
+            t: 5,
+            _pad: [0; 3],
+        }
+    }
+
+    /// Whether next event estimation samples the primitive: it emits light and is not a
+    /// constant medium
+    pub fn is_light(&self) -> bool {
+        self.material.is_emissive() && self.t != 5
+    }
+
+    /// Box around the primitive over the shutter interval `time0..time1`
+    pub fn bounding_box(&self, time0: f32, time1: f32, vertices: &[VertexPod]) -> AABB {
+        match self.t {
+            1 => {
+                let p0 = vertices[self.indices[0] as usize].position();
+                let p1 = vertices[self.indices[1] as usize].position();
+                let p2 = vertices[self.indices[2] as usize].position();
+                // Same padding as `triangle::MeshTriangle::bounding_box`
+                let pad = vec3(1e-4, 1e-4, 1e-4);
+                AABB {
+                    minimum: p0.min(p1).min(p2) - pad,
+                    maximum: p0.max(p1).max(p2) + pad,
+                }
+            }
+            // Every other kind ignores the vertex buffer
+            _ => EnumPrimitive::from(*self).bounding_box(time0, time1, &[]),
+        }
+    }
+}
+
+impl InstancePod {
+    /// Places object number `object` of the scene in the world by an invertible `transform`.
+    /// `material` replaces the materials of the object if given.
+    pub fn new(transform: Mat4, object: u32, material: Option<EnumMaterialPod>) -> Self {
+        let c = transform.inverse().to_cols_array_2d();
+        Self {
+            rows: [
+                [c[0][0], c[1][0], c[2][0], c[3][0]],
+                [c[0][1], c[1][1], c[2][1], c[3][1]],
+                [c[0][2], c[1][2], c[2][2], c[3][2]],
+            ],
+            indices: [object, material.is_some() as u32, 0, 0],
+            material: material.unwrap_or_default(),
+        }
+    }
+
+    pub fn object(&self) -> u32 {
+        self.indices[0]
+    }
+
+    /// Records the root of the BVH of the object once the BVHs are built
+    pub(crate) fn set_root(&mut self, root: u32) {
+        self.indices[2] = root;
+    }
+
+    /// The root of the BVH of the object, see `set_root`
+    pub(crate) fn root(&self) -> u32 {
+        self.indices[2]
+    }
+
+    fn world_to_object(&self) -> Mat4 {
+        let [r0, r1, r2] = self.rows;
+        Mat4::from_cols_array_2d(&[
+            [r0[0], r1[0], r2[0], 0.0],
+            [r0[1], r1[1], r2[1], 0.0],
+            [r0[2], r1[2], r2[2], 0.0],
+            [r0[3], r1[3], r2[3], 1.0],
+        ])
+    }
+
+    pub(crate) fn object_to_world(&self) -> Mat4 {
+        self.world_to_object().inverse()
+    }
+
+    /// Box around `aabb`, a box in object space, once placed in the world
+    pub fn bounding_box(&self, aabb: &AABB) -> AABB {
+        let transform = self.object_to_world();
+        let (min, max) = (aabb.minimum, aabb.maximum);
+        let mut minimum = Vec3::splat(f32::INFINITY);
+        let mut maximum = Vec3::splat(f32::NEG_INFINITY);
+        for corner in 0..8 {
+            let p = transform.transform_point3(vec3(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            ));
+            minimum = minimum.min(p);
+            maximum = maximum.max(p);
+        }
+        AABB { minimum, maximum }
+    }
+}
+
+impl VertexPod {
+    pub fn new(position: Vec3, normal: Vec3) -> Self {
+        Self {
+            position: [position.x, position.y, position.z, 0.0],
+            normal: [normal.x, normal.y, normal.z, 0.0],
+        }
+    }
+
+    /// A vertex whose triangles use their face normal
+    pub fn without_normal(position: Vec3) -> Self {
+        Self::new(position, Vec3::ZERO)
+    }
+
+    pub fn position(&self) -> Vec3 {
+        vec3(self.position[0], self.position[1], self.position[2])
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        vec3(self.normal[0], self.normal[1], self.normal[2])
+    }
 }
 
 impl EnumMaterialPod {
@@ -57,7 +345,8 @@ impl EnumMaterialPod {
         Self {
             data: [albedo.x, albedo.y, albedo.z, 0.0],
             t: 0,
-            _pad: [0.0, 0.0, 0.0],
+            texture: 0,
+            _pad: [0.0, 0.0],
         }
     }
 
@@ -65,7 +354,8 @@ impl EnumMaterialPod {
         Self {
             data: [albedo.x, albedo.y, albedo.z, fuzz],
             t: 1,
-            _pad: [0.0, 0.0, 0.0],
+            texture: 0,
+            _pad: [0.0, 0.0],
         }
     }
 
@@ -73,7 +363,270 @@ impl EnumMaterialPod {
         Self {
             data: [ir, 0.0, 0.0, 0.0],
             t: 2,
-            _pad: [0.0, 0.0, 0.0],
+            texture: 0,
+            _pad: [0.0, 0.0],
+        }
+    }
+
+    pub fn new_diffuse_light(emit: Vec3) -> Self {
+        Self {
+            data: [emit.x, emit.y, emit.z, 0.0],
+            t: 3,
+            texture: 0,
+            _pad: [0.0, 0.0],
+        }
+    }
+
+    /// Scatters uniformly in all directions, for constant media
+    pub fn new_isotropic(albedo: Vec3) -> Self {
+        Self {
+            data: [albedo.x, albedo.y, albedo.z, 0.0],
+            t: 4,
+            texture: 0,
+            _pad: [0.0, 0.0],
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.t == 3
+    }
+
+    /// Multiplies the albedo by entry `texture` of the texture buffer. Only lambertian and
+    /// metal materials are textured
+    pub fn with_texture(self, texture: u32) -> Self {
+        Self {
+            texture: texture + 1,
+            ..self
+        }
+    }
+}
+
+impl TexturePod {
+    pub fn new_solid(color: Vec3) -> Self {
+        Self {
+            data: [[color.x, color.y, color.z, 0.0], [0.0; 4]],
+            t: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Alternates between `even` and `odd` in cubes of side `scale`
+    pub fn new_checker(even: Vec3, odd: Vec3, scale: f32) -> Self {
+        Self {
+            data: [
+                [even.x, even.y, even.z, 1.0 / scale],
+                [odd.x, odd.y, odd.z, 0.0],
+            ],
+            t: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Veins of `color` along z, `frequency` radians apart, distorted by Perlin turbulence.
+    /// The tables of `perlin::texels` start at texel `first_texel`
+    pub fn new_marble(color: Vec3, frequency: f32, first_texel: u32) -> Self {
+        Self {
+            data: [[color.x, color.y, color.z, frequency], [0.0; 4]],
+            indices: [first_texel, 0, 0, 0],
+            t: 2,
+            ..Default::default()
+        }
+    }
+
+    /// Image of `width` x `height` texels starting at texel `first_texel`, top row first
+    pub fn new_image(width: u32, height: u32, first_texel: u32) -> Self {
+        Self {
+            indices: [first_texel, width, height, 0],
+            t: 3,
+            ..Default::default()
+        }
+    }
+}
+
+impl BackgroundPod {
+    /// The white to light blue gradient of "Ray Tracing in One Weekend"
+    pub fn new_sky() -> Self {
+        Self::new_gradient(vec3(1.0, 1.0, 1.0), vec3(0.5, 0.7, 1.0))
+    }
+
+    /// Blends from `bottom` for rays pointing down to `top` for rays pointing up
+    pub fn new_gradient(bottom: Vec3, top: Vec3) -> Self {
+        Self {
+            data0: [bottom.x, bottom.y, bottom.z, 0.0],
+            data1: [top.x, top.y, top.z, 0.0],
+            t: 0,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_solid(color: Vec3) -> Self {
+        Self {
+            data0: [color.x, color.y, color.z, 0.0],
+            t: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Equirectangular environment map of `width` x `height` texels, uploaded separately
+    pub fn new_environment_map(width: u32, height: u32, intensity: f32) -> Self {
+        Self {
+            data0: [intensity, 0.0, 0.0, 0.0],
+            t: 2,
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+}
+
+impl CameraPod {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        look_from: Vec3,
+        look_at: Vec3,
+        vup: Vec3,
+        vfov: f32,
+        aspect_ratio: f32,
+        aperture: f32,
+        focus_dist: f32,
+        time0: f32,
+        time1: f32,
+    ) -> Self {
+        Camera::new(
+            look_from,
+            look_at,
+            vup,
+            vfov,
+            aspect_ratio,
+            aperture,
+            focus_dist,
+            time0,
+            time1,
+        )
+        .into()
+    }
+}
+
+impl From<Camera> for CameraPod {
+    fn from(camera: Camera) -> Self {
+        let to_array = |v: Vec3| [v.x, v.y, v.z];
+        Self {
+            origin: to_array(camera.origin),
+            _pad0: 0.0,
+            lower_left_corner: to_array(camera.lower_left_corner),
+            _pad1: 0.0,
+            horizontal: to_array(camera.horizontal),
+            _pad2: 0.0,
+            vertical: to_array(camera.vertical),
+            _pad3: 0.0,
+            u: to_array(camera.u),
+            _pad4: 0.0,
+            v: to_array(camera.v),
+            _pad5: 0.0,
+            lens_radius: camera.lens_radius,
+            time0: camera.time0,
+            time1: camera.time1,
+            _pad6: 0.0,
+        }
+    }
+}
+
+impl From<CameraPod> for Camera {
+    fn from(pod: CameraPod) -> Self {
+        let to_vec3 = |v: [f32; 3]| vec3(v[0], v[1], v[2]);
+        Self {
+            origin: to_vec3(pod.origin),
+            lower_left_corner: to_vec3(pod.lower_left_corner),
+            horizontal: to_vec3(pod.horizontal),
+            vertical: to_vec3(pod.vertical),
+            u: to_vec3(pod.u),
+            v: to_vec3(pod.v),
+            lens_radius: pod.lens_radius,
+            time0: pod.time0,
+            time1: pod.time1,
+        }
+    }
+}
+
+impl From<EnumMaterialPod> for EnumMaterial {
+    fn from(pod: EnumMaterialPod) -> Self {
+        EnumMaterial::new(
+            vec4(pod.data[0], pod.data[1], pod.data[2], pod.data[3]),
+            pod.t,
+            pod.texture,
+        )
+    }
+}
+
+impl From<TexturePod> for EnumTexture {
+    fn from(pod: TexturePod) -> Self {
+        let [v0, v1] = pod.data;
+        EnumTexture::new(
+            v0.into(),
+            v1.into(),
+            uvec4(
+                pod.indices[0],
+                pod.indices[1],
+                pod.indices[2],
+                pod.indices[3],
+            ),
+            pod.t,
+        )
+    }
+}
+
+impl From<BackgroundPod> for EnumBackground {
+    fn from(pod: BackgroundPod) -> Self {
+        EnumBackground::new(
+            vec4(pod.data0[0], pod.data0[1], pod.data0[2], pod.data0[3]),
+            vec4(pod.data1[0], pod.data1[1], pod.data1[2], pod.data1[3]),
+            pod.t,
+            pod.width,
+            pod.height,
+        )
+    }
+}
+
+impl From<EnumPrimitivePod> for EnumPrimitive {
+    fn from(pod: EnumPrimitivePod) -> Self {
+        let [v0, v1, v2] = pod.data;
+        EnumPrimitive::new(
+            v0.into(),
+            v1.into(),
+            v2.into(),
+            uvec4(
+                pod.indices[0],
+                pod.indices[1],
+                pod.indices[2],
+                pod.indices[3],
+            ),
+            pod.material.into(),
+            pod.t,
+        )
+    }
+}
+
+impl From<InstancePod> for Instance {
+    fn from(pod: InstancePod) -> Self {
+        let [r0, r1, r2] = pod.rows;
+        Instance::new(
+            [r0.into(), r1.into(), r2.into()],
+            uvec4(
+                pod.indices[0],
+                pod.indices[1],
+                pod.indices[2],
+                pod.indices[3],
+            ),
+            pod.material.into(),
+        )
+    }
+}
+
+impl From<VertexPod> for Vertex {
+    fn from(pod: VertexPod) -> Self {
+        Self {
+            position: pod.position.into(),
+            normal: pod.normal.into(),
         }
     }
 }
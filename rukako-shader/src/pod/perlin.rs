@@ -0,0 +1,58 @@
+use rand::prelude::*;
+
+use crate::texture::PERLIN_POINT_COUNT;
+
+/// Random tables of a Perlin noise lattice, in the layout `texture::EnumTexture` reads: the
+/// unit gradients, then the permutations of the x, y and z axes.
+pub fn texels(rng: &mut impl Rng) -> Vec<[f32; 4]> {
+    let n = PERLIN_POINT_COUNT as usize;
+    let mut texels = Vec::with_capacity(2 * n);
+
+    for _ in 0..n {
+        let (x, y, z, length) = loop {
+            let x: f32 = rng.gen_range(-1.0..1.0);
+            let y: f32 = rng.gen_range(-1.0..1.0);
+            let z: f32 = rng.gen_range(-1.0..1.0);
+            let length = (x * x + y * y + z * z).sqrt();
+            // Reject tiny vectors, whose direction is dominated by rounding
+            if length > 1e-3 {
+                break (x, y, z, length);
+            }
+        };
+        texels.push([x / length, y / length, z / length, 0.0]);
+    }
+
+    let mut permutation = || {
+        let mut p: Vec<u32> = (0..PERLIN_POINT_COUNT).collect();
+        p.shuffle(rng);
+        p
+    };
+    let (px, py, pz) = (permutation(), permutation(), permutation());
+    for k in 0..n {
+        texels.push([px[k] as f32, py[k] as f32, pz[k] as f32, 0.0]);
+    }
+
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_unit_gradients_and_permutations() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let texels = texels(&mut rng);
+        let n = PERLIN_POINT_COUNT as usize;
+        assert_eq!(texels.len(), 2 * n);
+
+        for [x, y, z, _] in &texels[..n] {
+            assert!(((x * x + y * y + z * z).sqrt() - 1.0).abs() < 1e-5);
+        }
+        for axis in 0..3 {
+            let mut p: Vec<u32> = texels[n..].iter().map(|t| t[axis] as u32).collect();
+            p.sort_unstable();
+            assert_eq!(p, (0..PERLIN_POINT_COUNT).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use spirv_std::glam::{vec3, UVec4, Vec3, Vec4, Vec4Swizzles};
+
+use crate::{
+    aabb::AABB,
+    bool::Bool32,
+    hittable::{HitRecord, Hittable},
+    light::Light,
+    material::EnumMaterial,
+    medium::{BoxBoundary, ConstantMedium},
+    quad::{Quad, Rect},
+    rand::DefaultRng,
+    ray::Ray,
+    sphere::{MovingSphere, Sphere},
+    triangle::{MeshTriangle, Vertex},
+};
+
+/// Parameters of a primitive. Each kind reads the fields it needs.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct EnumPrimitiveData {
+    v0: Vec4,
+    v1: Vec4,
+    v2: Vec4,
+    u0: UVec4,
+}
+
+/// An entry of the world buffer. `t` selects the kind of primitive:
+///
+/// - 0: sphere, centre `v0.xyz` and radius `v0.w`
+/// - 1: triangle, vertex indices `u0.xyz` into the vertex buffer
+/// - 2: moving sphere, centre `v0.xyz` at time `v2.x` and `v1.xyz` at time `v2.y`, radius
+///   `v0.w`
+/// - 3: quad, corner `v0.xyz` and sides `v1.xyz` and `v2.xyz`
+/// - 4: axis-aligned rectangle from `v0.xyz` to `v1.xyz`, perpendicular to axis `u0.x`
+/// - 5: constant medium of density `-1 / v2.x`. Its boundary is a sphere like kind 0 if
+///   `u0.x` is 0, or else the box from `v0.xyz` to `v1.xyz`
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct EnumPrimitive {
+    data: EnumPrimitiveData,
+    material: EnumMaterial,
+    t: u32,
+}
+
+impl EnumPrimitive {
+    pub fn new(v0: Vec4, v1: Vec4, v2: Vec4, u0: UVec4, material: EnumMaterial, t: u32) -> Self {
+        Self {
+            data: EnumPrimitiveData { v0, v1, v2, u0 },
+            material,
+            t,
+        }
+    }
+
+    fn sphere(&self) -> Sphere {
+        Sphere {
+            center: self.data.v0.xyz(),
+            radius: self.data.v0.w,
+            matelial: self.material,
+        }
+    }
+
+    fn moving_sphere(&self) -> MovingSphere {
+        MovingSphere {
+            center0: self.data.v0.xyz(),
+            center1: self.data.v1.xyz(),
+            time0: self.data.v2.x,
+            time1: self.data.v2.y,
+            radius: self.data.v0.w,
+            material: self.material,
+        }
+    }
+
+    fn quad(&self) -> Quad {
+        Quad {
+            q: self.data.v0.xyz(),
+            u: self.data.v1.xyz(),
+            v: self.data.v2.xyz(),
+            material: self.material,
+        }
+    }
+
+    fn rect(&self) -> Rect {
+        Rect {
+            axis: self.data.u0.x,
+            minimum: self.data.v0.xyz(),
+            maximum: self.data.v1.xyz(),
+            material: self.material,
+        }
+    }
+
+    fn medium_sphere(&self) -> ConstantMedium<Sphere> {
+        ConstantMedium {
+            boundary: self.sphere(),
+            neg_inv_density: self.data.v2.x,
+            phase_function: self.material,
+        }
+    }
+
+    fn medium_box(&self) -> ConstantMedium<BoxBoundary> {
+        ConstantMedium {
+            boundary: BoxBoundary {
+                minimum: self.data.v0.xyz(),
+                maximum: self.data.v1.xyz(),
+            },
+            neg_inv_density: self.data.v2.x,
+            phase_function: self.material,
+        }
+    }
+
+    fn triangle<'a>(&self, vertices: &'a [Vertex]) -> MeshTriangle<'a> {
+        MeshTriangle {
+            indices: self.data.u0,
+            material: self.material,
+            vertices,
+        }
+    }
+
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit_record: &mut HitRecord,
+        vertices: &[Vertex],
+        rng: &mut DefaultRng,
+    ) -> Bool32 {
+        match self.t {
+            0 => self.sphere().hit(ray, t_min, t_max, hit_record),
+            1 => self.triangle(vertices).hit(ray, t_min, t_max, hit_record),
+            2 => self.moving_sphere().hit(ray, t_min, t_max, hit_record),
+            3 => self.quad().hit(ray, t_min, t_max, hit_record),
+            4 => self.rect().hit(ray, t_min, t_max, hit_record),
+            _ => match self.data.u0.x {
+                0 => self.medium_sphere().hit(ray, t_min, t_max, hit_record, rng),
+                _ => self.medium_box().hit(ray, t_min, t_max, hit_record, rng),
+            },
+        }
+    }
+
+    /// See `light::Light::pdf_value`. Constant media cannot be sampled and give 0
+    pub fn pdf_value(&self, ray: &Ray, vertices: &[Vertex]) -> f32 {
+        match self.t {
+            0 => self.sphere().pdf_value(ray),
+            1 => self.triangle(vertices).pdf_value(ray),
+            2 => self.moving_sphere().pdf_value(ray),
+            3 => self.quad().pdf_value(ray),
+            4 => self.rect().pdf_value(ray),
+            _ => 0.0,
+        }
+    }
+
+    /// See `light::Light::random`
+    pub fn random(
+        &self,
+        origin: Vec3,
+        time: f32,
+        vertices: &[Vertex],
+        rng: &mut DefaultRng,
+    ) -> Vec3 {
+        match self.t {
+            0 => self.sphere().random(origin, time, rng),
+            1 => self.triangle(vertices).random(origin, time, rng),
+            2 => self.moving_sphere().random(origin, time, rng),
+            3 => self.quad().random(origin, time, rng),
+            4 => self.rect().random(origin, time, rng),
+            _ => vec3(0.0, 0.0, 1.0),
+        }
+    }
+
+    pub fn bounding_box(&self, time0: f32, time1: f32, vertices: &[Vertex]) -> AABB {
+        match self.t {
+            0 => self.sphere().bounding_box(time0, time1),
+            1 => self.triangle(vertices).bounding_box(time0, time1),
+            2 => self.moving_sphere().bounding_box(time0, time1),
+            3 => self.quad().bounding_box(time0, time1),
+            4 => self.rect().bounding_box(time0, time1),
+            _ => match self.data.u0.x {
+                0 => self.medium_sphere().bounding_box(time0, time1),
+                _ => self.medium_box().bounding_box(time0, time1),
+            },
+        }
+    }
+}
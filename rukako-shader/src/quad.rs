@@ -0,0 +1,200 @@
+use spirv_std::glam::{vec2, vec3, Vec3};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+
+use crate::{
+    aabb::AABB,
+    bool::Bool32,
+    hittable::{HitRecord, Hittable},
+    light::Light,
+    material::EnumMaterial,
+    rand::DefaultRng,
+    ray::Ray,
+};
+
+/// A parallelogram with a corner at `q` and sides `u` and `v`. Its front faces `u × v`
+#[derive(Clone, Copy, Default)]
+pub struct Quad {
+    pub q: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: EnumMaterial,
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, hit_record: &mut HitRecord) -> Bool32 {
+        let n = self.u.cross(self.v);
+        let normal = n.normalize();
+
+        let denom = normal.dot(ray.direction);
+        // The quad is degenerate, which would give a NaN normal, or the ray is parallel to
+        // its plane
+        if Bool32::new(n.length_squared() == 0.0)
+            .or(Bool32::new(denom.abs() < 1e-8))
+            .into()
+        {
+            return Bool32::FALSE;
+        }
+
+        let t = (normal.dot(self.q) - normal.dot(ray.origin)) / denom;
+        if Bool32::new(t < t_min).or(Bool32::new(t_max < t)).into() {
+            return Bool32::FALSE;
+        }
+
+        // Coordinates of the hit along `u` and `v`
+        let position = ray.at(t);
+        let w = n / n.dot(n);
+        let planar = position - self.q;
+        let alpha = w.dot(planar.cross(self.v));
+        let beta = w.dot(self.u.cross(planar));
+        if Bool32::new(alpha < 0.0)
+            .or(Bool32::new(alpha > 1.0))
+            .or(Bool32::new(beta < 0.0))
+            .or(Bool32::new(beta > 1.0))
+            .into()
+        {
+            return Bool32::FALSE;
+        }
+
+        *hit_record = HitRecord::new(position, normal, t, ray, self.material);
+        hit_record.uv = vec2(alpha, beta);
+        Bool32::TRUE
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> AABB {
+        let p0 = self.q;
+        let p1 = self.q + self.u;
+        let p2 = self.q + self.v;
+        let p3 = self.q + self.u + self.v;
+
+        AABB {
+            minimum: p0.min(p1).min(p2).min(p3),
+            maximum: p0.max(p1).max(p2).max(p3),
+        }
+        .pad(1e-4)
+    }
+}
+
+impl Light for Quad {
+    /// Points are sampled uniformly over the area of the quad
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        let mut hit_record = HitRecord::default();
+        if (!self.hit(ray, 0.001, f32::INFINITY, &mut hit_record)).into() {
+            return 0.0;
+        }
+
+        let n = self.u.cross(self.v);
+        let area = n.length();
+        let length = ray.direction.length();
+        let distance_squared = hit_record.t * hit_record.t * length * length;
+        let cosine = (ray.direction.dot(n) / (length * area)).abs();
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: Vec3, _time: f32, rng: &mut DefaultRng) -> Vec3 {
+        self.q + rng.next_f32() * self.u + rng.next_f32() * self.v - origin
+    }
+}
+
+/// A rectangle perpendicular to `axis`, 0 for x, 1 for y and 2 for z, spanning from `minimum`
+/// to `maximum`. Both corners have the same component along `axis`, and the front faces its
+/// positive direction
+#[derive(Clone, Copy, Default)]
+pub struct Rect {
+    pub axis: u32,
+    pub minimum: Vec3,
+    pub maximum: Vec3,
+    pub material: EnumMaterial,
+}
+
+/// Rotates the components of `v` so that `axis` comes last
+fn to_local(axis: u32, v: Vec3) -> Vec3 {
+    match axis {
+        0 => vec3(v.y, v.z, v.x),
+        1 => vec3(v.z, v.x, v.y),
+        _ => v,
+    }
+}
+
+/// Inverse of `to_local`
+fn from_local(axis: u32, v: Vec3) -> Vec3 {
+    match axis {
+        0 => vec3(v.z, v.x, v.y),
+        1 => vec3(v.y, v.z, v.x),
+        _ => v,
+    }
+}
+
+impl Rect {
+    /// The same rectangle as a quad, facing either way
+    fn quad(&self) -> Quad {
+        let size = to_local(self.axis, self.maximum - self.minimum);
+        Quad {
+            q: self.minimum,
+            u: from_local(self.axis, vec3(size.x, 0.0, 0.0)),
+            v: from_local(self.axis, vec3(0.0, size.y, 0.0)),
+            material: self.material,
+        }
+    }
+}
+
+impl Hittable for Rect {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, hit_record: &mut HitRecord) -> Bool32 {
+        let origin = to_local(self.axis, ray.origin);
+        let direction = to_local(self.axis, ray.direction);
+        let minimum = to_local(self.axis, self.minimum);
+        let maximum = to_local(self.axis, self.maximum);
+
+        // The ray is parallel to the rectangle
+        if direction.z.abs() < 1e-8 {
+            return Bool32::FALSE;
+        }
+
+        let t = (minimum.z - origin.z) / direction.z;
+        if Bool32::new(t < t_min).or(Bool32::new(t_max < t)).into() {
+            return Bool32::FALSE;
+        }
+
+        let a = origin.x + t * direction.x;
+        let b = origin.y + t * direction.y;
+        if Bool32::new(a < minimum.x)
+            .or(Bool32::new(a > maximum.x))
+            .or(Bool32::new(b < minimum.y))
+            .or(Bool32::new(b > maximum.y))
+            .into()
+        {
+            return Bool32::FALSE;
+        }
+
+        *hit_record = HitRecord::new(
+            ray.at(t),
+            from_local(self.axis, vec3(0.0, 0.0, 1.0)),
+            t,
+            ray,
+            self.material,
+        );
+        hit_record.uv = vec2(
+            (a - minimum.x) / (maximum.x - minimum.x),
+            (b - minimum.y) / (maximum.y - minimum.y),
+        );
+        Bool32::TRUE
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> AABB {
+        AABB {
+            minimum: self.minimum,
+            maximum: self.maximum,
+        }
+        .pad(1e-4)
+    }
+}
+
+impl Light for Rect {
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        self.quad().pdf_value(ray)
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut DefaultRng) -> Vec3 {
+        self.quad().random(origin, time, rng)
+    }
+}
@@ -1,12 +1,18 @@
-use spirv_std::glam::{vec3, Vec3};
+use spirv_std::glam::{vec2, vec3, Vec3};
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
+use spirv_std::num_traits::FloatConst;
 
 use crate::{
-    aabb::AABB,
+    aabb::{surrounding_box, AABB},
     bool::Bool32,
     hittable::{HitRecord, Hittable},
+    light::Light,
     material::EnumMaterial,
+    math::{random_to_sphere, sphere_uv},
+    onb::Onb,
+    rand::DefaultRng,
+    ray::Ray,
 };
 
 #[derive(Clone, Copy, Default)]
@@ -18,13 +24,7 @@ pub struct Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(
-        &self,
-        ray: &crate::ray::Ray,
-        t_min: f32,
-        t_max: f32,
-        hit_record: &mut HitRecord,
-    ) -> Bool32 {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, hit_record: &mut HitRecord) -> Bool32 {
         let oc = ray.origin - self.center;
         let a = ray.direction.length_squared();
         let half_b = oc.dot(ray.direction);
@@ -52,14 +52,11 @@ impl Hittable for Sphere {
         }
 
         let position = ray.at(root);
+        let outward_normal = (position - self.center) / self.radius;
 
-        *hit_record = HitRecord::new(
-            position,
-            (position - self.center) / self.radius,
-            root,
-            ray,
-            self.matelial,
-        );
+        *hit_record = HitRecord::new(position, outward_normal, root, ray, self.matelial);
+        let (u, v) = sphere_uv(outward_normal);
+        hit_record.uv = vec2(u, v);
         Bool32::TRUE
     }
 
@@ -70,3 +67,83 @@ impl Hittable for Sphere {
         }
     }
 }
+
+impl Light for Sphere {
+    /// Directions are sampled uniformly within the cone the sphere subtends
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        let distance_squared = (self.center - ray.origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        // The origin is inside, where the sphere subtends no cone
+        if distance_squared <= radius_squared {
+            return 0.0;
+        }
+
+        let mut hit_record = HitRecord::default();
+        if (!self.hit(ray, 0.001, f32::INFINITY, &mut hit_record)).into() {
+            return 0.0;
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * f32::PI() * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: Vec3, _time: f32, rng: &mut DefaultRng) -> Vec3 {
+        let direction = self.center - origin;
+        Onb::from_w(direction).local(random_to_sphere(
+            self.radius,
+            direction.length_squared(),
+            rng,
+        ))
+    }
+}
+
+/// A sphere moving linearly from `center0` at `time0` to `center1` at `time1`
+#[derive(Clone, Copy, Default)]
+pub struct MovingSphere {
+    pub center0: Vec3,
+    pub center1: Vec3,
+    pub time0: f32,
+    pub time1: f32,
+    pub radius: f32,
+    pub material: EnumMaterial,
+}
+
+impl MovingSphere {
+    pub fn center(&self, time: f32) -> Vec3 {
+        self.center0
+            + ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
+    }
+
+    fn at(&self, time: f32) -> Sphere {
+        Sphere {
+            center: self.center(time),
+            radius: self.radius,
+            matelial: self.material,
+        }
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, hit_record: &mut HitRecord) -> Bool32 {
+        self.at(ray.time).hit(ray, t_min, t_max, hit_record)
+    }
+
+    /// The motion is linear, so the boxes at both ends of the interval bound the sweep
+    fn bounding_box(&self, time0: f32, time1: f32) -> AABB {
+        surrounding_box(
+            self.at(time0).bounding_box(time0, time1),
+            self.at(time1).bounding_box(time0, time1),
+        )
+    }
+}
+
+impl Light for MovingSphere {
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        self.at(ray.time).pdf_value(ray)
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut DefaultRng) -> Vec3 {
+        self.at(time).random(origin, time, rng)
+    }
+}
@@ -0,0 +1,176 @@
+use spirv_std::glam::{vec3, UVec4, Vec2, Vec3, Vec4, Vec4Swizzles};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+
+use crate::hittable::HitRecord;
+
+/// Number of gradients of a Perlin noise lattice. A Perlin texture reads as many gradients
+/// followed by as many permutation entries from the texel buffer.
+pub const PERLIN_POINT_COUNT: u32 = 256;
+
+/// An entry of the texture buffer. `t` selects the kind of texture:
+///
+/// - 0: solid colour `v0.xyz`
+/// - 1: 3D checker of `v0.xyz` and `v1.xyz` with cubes of side `1 / v0.w`
+/// - 2: marble of colour `v0.xyz` and frequency `v0.w`, made of Perlin turbulence whose
+///   tables start at texel `u0.x`
+/// - 3: image of `u0.y` x `u0.z` texels starting at texel `u0.x`, top row first
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct EnumTexture {
+    v0: Vec4,
+    v1: Vec4,
+    u0: UVec4,
+    t: u32,
+}
+
+/// The texture buffer and the texel buffer it indexes. The texels of the environment map
+/// come first.
+pub struct Textures<'a> {
+    pub records: &'a [EnumTexture],
+    pub texels: &'a [Vec4],
+}
+
+struct Solid<'a> {
+    data: &'a EnumTexture,
+}
+
+struct Checker<'a> {
+    data: &'a EnumTexture,
+}
+
+struct Marble<'a> {
+    data: &'a EnumTexture,
+}
+
+struct Image<'a> {
+    data: &'a EnumTexture,
+}
+
+impl<'a> Solid<'a> {
+    fn value(&self) -> Vec3 {
+        self.data.v0.xyz()
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn value(&self, position: Vec3) -> Vec3 {
+        let p = (self.data.v0.w * position).floor();
+        if ((p.x + p.y + p.z) as i32) & 1 == 0 {
+            self.data.v0.xyz()
+        } else {
+            self.data.v1.xyz()
+        }
+    }
+}
+
+impl<'a> Marble<'a> {
+    fn value(&self, position: Vec3, texels: &[Vec4]) -> Vec3 {
+        let perlin = Perlin {
+            first: self.data.u0.x,
+            texels,
+        };
+        let phase = self.data.v0.w * position.z + 10.0 * perlin.turbulence(position);
+        self.data.v0.xyz() * 0.5 * (1.0 + phase.sin())
+    }
+}
+
+impl<'a> Image<'a> {
+    /// Nearest texel lookup, clamped at the edges
+    fn value(&self, uv: Vec2, texels: &[Vec4]) -> Vec3 {
+        let width = self.data.u0.y;
+        let height = self.data.u0.z;
+
+        let u = uv.x.clamp(0.0, 1.0);
+        let v = uv.y.clamp(0.0, 1.0);
+        let i = ((u * width as f32) as u32).min(width - 1);
+        let j = (((1.0 - v) * height as f32) as u32).min(height - 1);
+
+        texels[(self.data.u0.x + j * width + i) as usize].xyz()
+    }
+}
+
+/// Gradient noise over the tables of "Ray Tracing: The Next Week". Texel `first + k` holds
+/// gradient `k` and texel `first + PERLIN_POINT_COUNT + k` entry `k` of the permutations of
+/// the x, y and z axes.
+struct Perlin<'a> {
+    first: u32,
+    texels: &'a [Vec4],
+}
+
+impl<'a> Perlin<'a> {
+    fn permutation(&self, k: i32) -> Vec4 {
+        let k = (k & (PERLIN_POINT_COUNT as i32 - 1)) as u32;
+        self.texels[(self.first + PERLIN_POINT_COUNT + k) as usize]
+    }
+
+    fn noise(&self, p: Vec3) -> f32 {
+        let cell = p.floor();
+        let f = p - cell;
+        // Hermite smoothing of the interpolation weights
+        let one = vec3(1.0, 1.0, 1.0);
+        let s = f * f * (3.0 * one - 2.0 * f);
+
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let k = self.permutation(cell.x as i32 + di).x as u32
+                        ^ self.permutation(cell.y as i32 + dj).y as u32
+                        ^ self.permutation(cell.z as i32 + dk).z as u32;
+                    let gradient = self.texels[(self.first + k) as usize].xyz();
+                    let corner = vec3(di as f32, dj as f32, dk as f32);
+                    let w = corner * s + (one - corner) * (one - s);
+                    accum += w.x * w.y * w.z * gradient.dot(f - corner);
+                }
+            }
+        }
+        accum
+    }
+
+    /// Sum of 7 octaves of noise
+    fn turbulence(&self, p: Vec3) -> f32 {
+        let mut accum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..7 {
+            accum += weight * self.noise(p);
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        accum.abs()
+    }
+}
+
+impl EnumTexture {
+    pub fn new(v0: Vec4, v1: Vec4, u0: UVec4, t: u32) -> Self {
+        Self { v0, v1, u0, t }
+    }
+
+    pub fn value(&self, uv: Vec2, position: Vec3, texels: &[Vec4]) -> Vec3 {
+        match self.t {
+            0 => Solid { data: self }.value(),
+            1 => Checker { data: self }.value(position),
+            2 => Marble { data: self }.value(position, texels),
+            _ => Image { data: self }.value(uv, texels),
+        }
+    }
+}
+
+impl<'a> Textures<'a> {
+    /// `color` modulated by the texture at the hit. `texture` is 0 for none, or else one
+    /// more than the index of the texture. Checker and marble textures are evaluated in the
+    /// space of the object hit, so they move with instances.
+    pub fn modulate(&self, color: Vec3, texture: u32, hit_record: &HitRecord) -> Vec3 {
+        if texture == 0 {
+            color
+        } else {
+            color
+                * self.records[(texture - 1) as usize].value(
+                    hit_record.uv,
+                    hit_record.local_position,
+                    self.texels,
+                )
+        }
+    }
+}
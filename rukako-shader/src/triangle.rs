@@ -0,0 +1,127 @@
+use spirv_std::glam::{vec2, vec3, UVec4, Vec3, Vec4, Vec4Swizzles};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+
+use crate::{
+    aabb::AABB,
+    bool::Bool32,
+    hittable::{HitRecord, Hittable},
+    light::Light,
+    material::EnumMaterial,
+    rand::DefaultRng,
+    ray::Ray,
+};
+
+/// A vertex of a mesh. A zero normal means the mesh has none and the face normal is used.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct Vertex {
+    pub position: Vec4,
+    pub normal: Vec4,
+}
+
+/// A triangle referencing three entries of the vertex buffer in `indices.xyz`
+pub struct MeshTriangle<'a> {
+    pub indices: UVec4,
+    pub material: EnumMaterial,
+    pub vertices: &'a [Vertex],
+}
+
+impl<'a> MeshTriangle<'a> {
+    fn vertex(&self, i: u32) -> &Vertex {
+        &self.vertices[i as usize]
+    }
+}
+
+impl<'a> Hittable for MeshTriangle<'a> {
+    /// Möller–Trumbore intersection
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, hit_record: &mut HitRecord) -> Bool32 {
+        let indices = self.indices;
+        let v0 = self.vertex(indices.x);
+        let v1 = self.vertex(indices.y);
+        let v2 = self.vertex(indices.z);
+
+        let p0 = v0.position.xyz();
+        let edge1 = v1.position.xyz() - p0;
+        let edge2 = v2.position.xyz() - p0;
+
+        let p = ray.direction.cross(edge2);
+        let det = edge1.dot(p);
+        // The ray is parallel to the triangle
+        if det.abs() < 1e-8 {
+            return Bool32::FALSE;
+        }
+        let inv_det = 1.0 / det;
+
+        let s = ray.origin - p0;
+        let u = s.dot(p) * inv_det;
+        if Bool32::new(u < 0.0).or(Bool32::new(u > 1.0)).into() {
+            return Bool32::FALSE;
+        }
+
+        let q = s.cross(edge1);
+        let v = ray.direction.dot(q) * inv_det;
+        if Bool32::new(v < 0.0).or(Bool32::new(u + v > 1.0)).into() {
+            return Bool32::FALSE;
+        }
+
+        let t = edge2.dot(q) * inv_det;
+        if Bool32::new(t < t_min).or(Bool32::new(t_max < t)).into() {
+            return Bool32::FALSE;
+        }
+
+        let normal = (1.0 - u - v) * v0.normal.xyz() + u * v1.normal.xyz() + v * v2.normal.xyz();
+        let normal = if normal.length_squared() > 0.0 {
+            normal.normalize()
+        } else {
+            edge1.cross(edge2).normalize()
+        };
+
+        *hit_record = HitRecord::new(ray.at(t), normal, t, ray, self.material);
+        hit_record.uv = vec2(u, v);
+        Bool32::TRUE
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> AABB {
+        let indices = self.indices;
+        let p0 = self.vertex(indices.x).position.xyz();
+        let p1 = self.vertex(indices.y).position.xyz();
+        let p2 = self.vertex(indices.z).position.xyz();
+
+        // Axis-aligned triangles would give a box of zero thickness
+        let pad = vec3(1e-4, 1e-4, 1e-4);
+        AABB {
+            minimum: p0.min(p1).min(p2) - pad,
+            maximum: p0.max(p1).max(p2) + pad,
+        }
+    }
+}
+
+impl<'a> Light for MeshTriangle<'a> {
+    /// Points are sampled uniformly over the area of the triangle
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        let mut hit_record = HitRecord::default();
+        if (!self.hit(ray, 0.001, f32::INFINITY, &mut hit_record)).into() {
+            return 0.0;
+        }
+
+        let p0 = self.vertex(self.indices.x).position.xyz();
+        let p1 = self.vertex(self.indices.y).position.xyz();
+        let p2 = self.vertex(self.indices.z).position.xyz();
+        let n = (p1 - p0).cross(p2 - p0);
+        let area = 0.5 * n.length();
+        let length = ray.direction.length();
+        let distance_squared = hit_record.t * hit_record.t * length * length;
+        let cosine = (ray.direction.dot(n) / (length * n.length())).abs();
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: Vec3, _time: f32, rng: &mut DefaultRng) -> Vec3 {
+        let p0 = self.vertex(self.indices.x).position.xyz();
+        let p1 = self.vertex(self.indices.y).position.xyz();
+        let p2 = self.vertex(self.indices.z).position.xyz();
+        let r1 = rng.next_f32().sqrt();
+        let r2 = rng.next_f32();
+        (1.0 - r1) * p0 + r1 * (1.0 - r2) * p1 + r1 * r2 * p2 - origin
+    }
+}
@@ -0,0 +1,16 @@
+use crate::{instance::Instance, primitive::EnumPrimitive, texture::Textures, triangle::Vertex};
+
+/// Every primitive `bvh::BVH::hit` can reach, and the textures of their materials. Leaves of
+/// the BVH index `primitives`.
+pub struct World<'a> {
+    pub primitives: &'a [EnumPrimitive],
+    /// Vertices indexed by triangle primitives
+    pub vertices: &'a [Vertex],
+    /// Instances referenced by the leaves of the top-level BVH
+    pub instances: &'a [Instance],
+    pub textures: Textures<'a>,
+    /// Indices into `primitives` of the first `n_lights` entries, every emissive primitive
+    /// in world space
+    pub lights: &'a [u32],
+    pub n_lights: u32,
+}
@@ -0,0 +1,695 @@
+//! Renders small fixed-seed scenes through the shader code running on the CPU and
+//! compares them against the reference images in `tests/golden`.
+//!
+//! Set `RUKAKO_BLESS=1` to overwrite the references with the current output.
+//! On failure the actual image and a diff image are written to `target/golden`.
+
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use image::{Rgb, RgbImage};
+use rand::prelude::*;
+use rukako_shader::{
+    background::EnumBackground,
+    bvh::BVHNode,
+    camera::Camera,
+    cpu,
+    instance::Instance,
+    pod::{
+        bvh::{create_instanced_bvh, BVHBuilder, DEFAULT_MAX_LEAF_SIZE},
+        light::light_list,
+        perlin, BackgroundPod, CameraPod, EnumMaterialPod, EnumPrimitivePod, InstancePod,
+        TexturePod, VertexPod,
+    },
+    primitive::EnumPrimitive,
+    texture::{EnumTexture, Textures},
+    triangle::Vertex,
+    world::World,
+};
+use spirv_std::glam::{vec3, vec4, Mat4, Quat, Vec3, Vec4};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+const N_SAMPLES: usize = 16;
+
+// Small enough to catch shading changes, large enough to tolerate libm differences
+// between platforms.
+const MAX_RMSE: f32 = 0.02;
+
+fn camera(look_from: Vec3, look_at: Vec3, vfov: f32) -> CameraPod {
+    CameraPod::new(
+        look_from,
+        look_at,
+        vec3(0.0, 1.0, 0.0),
+        vfov.to_radians(),
+        WIDTH as f32 / HEIGHT as f32,
+        0.0,
+        1.0,
+        0.0,
+        1.0,
+    )
+}
+
+/// Everything `render` needs, defaulting to an empty world under the sky
+struct GoldenScene {
+    camera: CameraPod,
+    world: Vec<EnumPrimitivePod>,
+    /// Ranges of `world` placed by `instances`
+    objects: Vec<Range<usize>>,
+    instances: Vec<InstancePod>,
+    vertices: Vec<VertexPod>,
+    background: BackgroundPod,
+    textures: Vec<TexturePod>,
+    /// Texels of the environment map, if any, followed by those of `textures`
+    texels: Vec<Vec4>,
+    seed: u64,
+}
+
+impl Default for GoldenScene {
+    fn default() -> Self {
+        Self {
+            camera: camera(vec3(0.0, 2.0, 6.0), vec3(0.0, 1.0, 0.0), 40.0),
+            world: Vec::new(),
+            objects: Vec::new(),
+            instances: Vec::new(),
+            vertices: Vec::new(),
+            background: BackgroundPod::new_sky(),
+            textures: Vec::new(),
+            texels: Vec::new(),
+            seed: 0,
+        }
+    }
+}
+
+fn render(scene: &GoldenScene) -> RgbImage {
+    let mut rng = StdRng::seed_from_u64(scene.seed);
+
+    let mut world = scene.world.clone();
+    let mut instances = scene.instances.clone();
+    let bvh = create_instanced_bvh(
+        &mut world,
+        &scene.objects,
+        &mut instances,
+        &scene.vertices,
+        0.0,
+        1.0,
+        BVHBuilder::default(),
+        DEFAULT_MAX_LEAF_SIZE,
+        &mut rng,
+    )
+    .unwrap();
+
+    let lights = light_list(&world, &scene.objects);
+    let primitives: Vec<EnumPrimitive> = world.into_iter().map(Into::into).collect();
+    let vertices: Vec<Vertex> = scene.vertices.iter().copied().map(Into::into).collect();
+    let instances: Vec<Instance> = instances.into_iter().map(Into::into).collect();
+    let textures: Vec<EnumTexture> = scene.textures.iter().copied().map(Into::into).collect();
+    let world = World {
+        primitives: &primitives,
+        vertices: &vertices,
+        instances: &instances,
+        textures: Textures {
+            records: &textures,
+            texels: &scene.texels,
+        },
+        lights: &lights,
+        n_lights: lights.len() as u32,
+    };
+    let bvh: Vec<BVHNode> = bvh.into_iter().map(Into::into).collect();
+    let camera: Camera = scene.camera.into();
+    let background: EnumBackground = scene.background.into();
+
+    let out = cpu::render(
+        WIDTH,
+        HEIGHT,
+        N_SAMPLES,
+        &world,
+        &bvh,
+        &camera,
+        &background,
+        &mut rng,
+        |_| {},
+    );
+
+    let scale = 1.0 / N_SAMPLES as f32;
+    RgbImage::from_fn(WIDTH as u32, HEIGHT as u32, |x, y| {
+        let c = out[y as usize * WIDTH + x as usize];
+        let f = |v: f32| (256.0 * (v * scale).sqrt().clamp(0.0, 0.999)) as u8;
+        Rgb([f(c.x), f(c.y), f(c.z)])
+    })
+}
+
+fn rmse(a: &RgbImage, b: &RgbImage) -> f32 {
+    let sum: f32 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw().iter())
+        .map(|(&a, &b)| {
+            let d = (a as f32 - b as f32) / 255.0;
+            d * d
+        })
+        .sum();
+    (sum / a.as_raw().len() as f32).sqrt()
+}
+
+fn diff_image(a: &RgbImage, b: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let pa = a.get_pixel(x, y);
+        let pb = b.get_pixel(x, y);
+        let mut p = [0; 3];
+        for i in 0..3 {
+            // Amplify so that small differences are visible
+            p[i] = ((pa[i] as i32 - pb[i] as i32).abs() * 8).min(255) as u8;
+        }
+        Rgb(p)
+    })
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+fn check(name: &str, actual: RgbImage) {
+    let reference_path = golden_dir().join(format!("{}.png", name));
+
+    if std::env::var_os("RUKAKO_BLESS").is_some() {
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|e| panic!("failed to open {}: {}", reference_path.display(), e))
+        .to_rgb8();
+    assert_eq!(reference.dimensions(), actual.dimensions());
+
+    let error = rmse(&reference, &actual);
+    if error > MAX_RMSE {
+        std::fs::create_dir_all(output_dir()).unwrap();
+        let actual_path = output_dir().join(format!("{}.actual.png", name));
+        let diff_path = output_dir().join(format!("{}.diff.png", name));
+        actual.save(&actual_path).unwrap();
+        diff_image(&reference, &actual).save(&diff_path).unwrap();
+        panic!(
+            "{}: RMSE {} exceeds {}. See {} and {}",
+            name,
+            error,
+            MAX_RMSE,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn ground() -> EnumPrimitivePod {
+    EnumPrimitivePod::new_sphere(
+        vec3(0.0, -1000.0, 0.0),
+        1000.0,
+        EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5)),
+    )
+}
+
+#[test]
+fn lambertian() {
+    let world = vec![
+        ground(),
+        EnumPrimitivePod::new_sphere(
+            vec3(0.0, 1.0, 0.0),
+            1.0,
+            EnumMaterialPod::new_lambertian(vec3(0.7, 0.3, 0.3)),
+        ),
+    ];
+
+    check(
+        "lambertian",
+        render(&GoldenScene {
+            world,
+            seed: 1,
+            ..Default::default()
+        }),
+    );
+}
+
+#[test]
+fn metal() {
+    let world = vec![
+        ground(),
+        EnumPrimitivePod::new_sphere(
+            vec3(-1.1, 1.0, 0.0),
+            1.0,
+            EnumMaterialPod::new_metal(vec3(0.8, 0.8, 0.8), 0.0),
+        ),
+        EnumPrimitivePod::new_sphere(
+            vec3(1.1, 1.0, 0.0),
+            1.0,
+            EnumMaterialPod::new_metal(vec3(0.8, 0.6, 0.2), 0.5),
+        ),
+    ];
+
+    check(
+        "metal",
+        render(&GoldenScene {
+            camera: camera(vec3(0.0, 2.0, 6.0), vec3(0.0, 1.0, 0.0), 50.0),
+            world,
+            seed: 2,
+            ..Default::default()
+        }),
+    );
+}
+
+#[test]
+fn dielectric() {
+    let world = vec![
+        ground(),
+        EnumPrimitivePod::new_sphere(
+            vec3(0.0, 1.0, 0.0),
+            1.0,
+            EnumMaterialPod::new_dielectric(1.5),
+        ),
+        EnumPrimitivePod::new_sphere(
+            vec3(0.0, 1.0, -3.0),
+            1.0,
+            EnumMaterialPod::new_lambertian(vec3(0.1, 0.2, 0.5)),
+        ),
+    ];
+
+    check(
+        "dielectric",
+        render(&GoldenScene {
+            camera: camera(vec3(0.0, 1.5, 6.0), vec3(0.0, 1.0, 0.0), 40.0),
+            world,
+            seed: 3,
+            ..Default::default()
+        }),
+    );
+}
+
+#[test]
+fn bvh_grid() {
+    let mut world = vec![ground()];
+    for a in -4i32..4 {
+        for b in -4..4 {
+            let material = match (a + b).rem_euclid(3) {
+                0 => EnumMaterialPod::new_lambertian(vec3(0.8, 0.2, 0.1)),
+                1 => EnumMaterialPod::new_metal(vec3(0.7, 0.7, 0.7), 0.1),
+                _ => EnumMaterialPod::new_dielectric(1.5),
+            };
+            world.push(EnumPrimitivePod::new_sphere(
+                vec3(a as f32 + 0.5, 0.3, b as f32 + 0.5),
+                0.3,
+                material,
+            ));
+        }
+    }
+
+    check(
+        "bvh_grid",
+        render(&GoldenScene {
+            camera: camera(vec3(6.0, 4.0, 6.0), vec3(0.0, 0.0, 0.0), 45.0),
+            world,
+            seed: 4,
+            ..Default::default()
+        }),
+    );
+}
+
+#[test]
+fn same_seed_is_bit_identical() {
+    let scene = GoldenScene {
+        world: vec![
+            ground(),
+            EnumPrimitivePod::new_sphere(
+                vec3(0.0, 1.0, 0.0),
+                1.0,
+                EnumMaterialPod::new_metal(vec3(0.8, 0.8, 0.8), 0.3),
+            ),
+        ],
+        seed: 5,
+        ..Default::default()
+    };
+
+    assert_eq!(render(&scene), render(&scene));
+}
+
+#[test]
+fn diffuse_light() {
+    let world = vec![
+        ground(),
+        EnumPrimitivePod::new_sphere(
+            vec3(0.0, 1.0, 0.0),
+            1.0,
+            EnumMaterialPod::new_lambertian(vec3(0.7, 0.3, 0.3)),
+        ),
+        EnumPrimitivePod::new_sphere(
+            vec3(0.0, 3.5, 0.0),
+            1.0,
+            EnumMaterialPod::new_diffuse_light(vec3(4.0, 4.0, 4.0)),
+        ),
+    ];
+
+    check(
+        "diffuse_light",
+        render(&GoldenScene {
+            camera: camera(vec3(0.0, 2.0, 8.0), vec3(0.0, 2.0, 0.0), 50.0),
+            world,
+            background: BackgroundPod::new_solid(Vec3::ZERO),
+            seed: 6,
+            ..Default::default()
+        }),
+    );
+}
+
+#[test]
+fn gradient_background() {
+    let world = vec![EnumPrimitivePod::new_sphere(
+        vec3(0.0, 0.0, 0.0),
+        1.0,
+        EnumMaterialPod::new_metal(vec3(0.9, 0.9, 0.9), 0.0),
+    )];
+
+    check(
+        "gradient_background",
+        render(&GoldenScene {
+            camera: camera(vec3(0.0, 0.0, 5.0), vec3(0.0, 0.0, 0.0), 50.0),
+            world,
+            background: BackgroundPod::new_gradient(vec3(0.8, 0.3, 0.1), vec3(0.1, 0.2, 0.9)),
+            seed: 7,
+            ..Default::default()
+        }),
+    );
+}
+
+#[test]
+fn environment_map() {
+    // 8 x 4 texels: a different colour for each quadrant of the sphere of directions
+    let texels: Vec<Vec4> = (0..4)
+        .flat_map(|j| {
+            (0..8).map(move |i| match (i < 4, j < 2) {
+                (true, true) => vec4(1.0, 0.2, 0.2, 1.0),
+                (false, true) => vec4(0.2, 1.0, 0.2, 1.0),
+                (true, false) => vec4(0.2, 0.2, 1.0, 1.0),
+                (false, false) => vec4(1.0, 1.0, 0.2, 1.0),
+            })
+        })
+        .collect();
+    let world = vec![EnumPrimitivePod::new_sphere(
+        vec3(0.0, 0.0, 0.0),
+        1.0,
+        EnumMaterialPod::new_metal(vec3(0.9, 0.9, 0.9), 0.0),
+    )];
+
+    check(
+        "environment_map",
+        render(&GoldenScene {
+            camera: camera(vec3(0.0, 0.0, 5.0), vec3(0.0, 0.0, 0.0), 60.0),
+            world,
+            background: BackgroundPod::new_environment_map(8, 4, 0.8),
+            texels,
+            seed: 8,
+            ..Default::default()
+        }),
+    );
+}
+
+#[test]
+fn triangle_mesh() {
+    let ground = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+    let red = EnumMaterialPod::new_lambertian(vec3(0.8, 0.2, 0.1));
+    let mirror = EnumMaterialPod::new_metal(vec3(0.8, 0.8, 0.8), 0.0);
+
+    // A flat-shaded pyramid next to a smooth-shaded mirror quad
+    let mut vertices = vec![
+        VertexPod::without_normal(vec3(-1.5, 0.0, -0.5)),
+        VertexPod::without_normal(vec3(-0.5, 0.0, -0.5)),
+        VertexPod::without_normal(vec3(-0.5, 0.0, 0.5)),
+        VertexPod::without_normal(vec3(-1.5, 0.0, 0.5)),
+        VertexPod::without_normal(vec3(-1.0, 1.2, 0.0)),
+    ];
+    let mut world = vec![EnumPrimitivePod::new_sphere(
+        vec3(0.0, -1000.0, 0.0),
+        1000.0,
+        ground,
+    )];
+    world.extend(
+        [[0, 4, 1], [1, 4, 2], [2, 4, 3], [3, 4, 0]]
+            .iter()
+            .map(|&indices| EnumPrimitivePod::new_triangle(indices, red)),
+    );
+
+    let base = vertices.len() as u32;
+    for &(x, y, bend) in &[
+        (0.3, 0.0, -0.3),
+        (1.5, 0.0, 0.3),
+        (1.5, 1.2, 0.3),
+        (0.3, 1.2, -0.3),
+    ] {
+        vertices.push(VertexPod::new(
+            vec3(x, y, -0.6),
+            vec3(bend, 0.0, 1.0).normalize(),
+        ));
+    }
+    world.push(EnumPrimitivePod::new_triangle(
+        [base, base + 1, base + 2],
+        mirror,
+    ));
+    world.push(EnumPrimitivePod::new_triangle(
+        [base, base + 2, base + 3],
+        mirror,
+    ));
+
+    let img = render(&GoldenScene {
+        camera: camera(vec3(0.0, 1.5, 5.0), vec3(0.0, 0.5, 0.0), 35.0),
+        world,
+        vertices,
+        seed: 8,
+        ..Default::default()
+    });
+    check("triangle_mesh", img);
+}
+
+#[test]
+fn motion_blur() {
+    let material = EnumMaterialPod::new_lambertian(vec3(0.8, 0.3, 0.1));
+    // One sphere rising during the shutter next to a static one
+    let world = vec![
+        ground(),
+        EnumPrimitivePod::new_moving_sphere(
+            vec3(-0.6, 0.5, 0.0),
+            vec3(-0.6, 1.1, 0.0),
+            0.0,
+            1.0,
+            0.5,
+            material,
+        ),
+        EnumPrimitivePod::new_sphere(vec3(0.6, 0.5, 0.0), 0.5, material),
+    ];
+    let img = render(&GoldenScene {
+        camera: camera(vec3(0.0, 1.0, 4.0), vec3(0.0, 0.7, 0.0), 40.0),
+        world,
+        seed: 9,
+        ..Default::default()
+    });
+    check("motion_blur", img);
+}
+
+#[test]
+fn cornell_box() {
+    let red = EnumMaterialPod::new_lambertian(vec3(0.65, 0.05, 0.05));
+    let white = EnumMaterialPod::new_lambertian(vec3(0.73, 0.73, 0.73));
+    let green = EnumMaterialPod::new_lambertian(vec3(0.12, 0.45, 0.15));
+    let light = EnumMaterialPod::new_diffuse_light(vec3(15.0, 15.0, 15.0));
+
+    let mut world = vec![
+        EnumPrimitivePod::new_rect(0, vec3(1.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0), green),
+        EnumPrimitivePod::new_rect(0, vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 1.0), red),
+        EnumPrimitivePod::new_rect(1, vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 1.0), white),
+        EnumPrimitivePod::new_rect(1, vec3(0.0, 1.0, 0.0), vec3(1.0, 1.0, 1.0), white),
+        EnumPrimitivePod::new_rect(2, vec3(0.0, 0.0, 1.0), vec3(1.0, 1.0, 1.0), white),
+        EnumPrimitivePod::new_quad(
+            vec3(0.35, 0.999, 0.4),
+            vec3(0.3, 0.0, 0.0),
+            vec3(0.0, 0.0, 0.25),
+            light,
+        ),
+    ];
+    world.extend_from_slice(&EnumPrimitivePod::new_box(
+        vec3(0.55, 0.0, 0.2),
+        vec3(0.85, 0.3, 0.5),
+        white,
+    ));
+    world.extend_from_slice(&EnumPrimitivePod::new_box(
+        vec3(0.15, 0.0, 0.45),
+        vec3(0.45, 0.6, 0.75),
+        white,
+    ));
+
+    let img = render(&GoldenScene {
+        camera: camera(vec3(0.5, 0.5, -1.4), vec3(0.5, 0.5, 0.0), 40.0),
+        world,
+        background: BackgroundPod::new_solid(Vec3::ZERO),
+        seed: 10,
+        ..Default::default()
+    });
+    check("cornell_box", img);
+}
+
+#[test]
+fn instances() {
+    let material = EnumMaterialPod::new_lambertian(vec3(0.7, 0.6, 0.2));
+    let mut world = vec![ground()];
+    // A unit cube with a sphere on top, shared by every instance
+    let start = world.len();
+    world.extend_from_slice(&EnumPrimitivePod::new_box(
+        vec3(-0.5, 0.0, -0.5),
+        vec3(0.5, 1.0, 0.5),
+        material,
+    ));
+    world.push(EnumPrimitivePod::new_sphere(
+        vec3(0.0, 1.3, 0.0),
+        0.3,
+        material,
+    ));
+    let object = start..world.len();
+
+    let instances = vec![
+        InstancePod::new(Mat4::from_translation(vec3(-1.5, 0.0, 0.0)), 0, None),
+        InstancePod::new(
+            Mat4::from_rotation_translation(Quat::from_rotation_y(0.6), Vec3::ZERO),
+            0,
+            Some(EnumMaterialPod::new_metal(vec3(0.8, 0.8, 0.8), 0.1)),
+        ),
+        InstancePod::new(
+            Mat4::from_scale_rotation_translation(
+                vec3(0.6, 1.5, 0.6),
+                Quat::from_rotation_z(-0.3),
+                vec3(1.5, 0.0, 0.0),
+            ),
+            0,
+            Some(EnumMaterialPod::new_lambertian(vec3(0.2, 0.3, 0.8))),
+        ),
+    ];
+
+    let img = render(&GoldenScene {
+        camera: camera(vec3(0.0, 2.0, 6.0), vec3(0.0, 0.8, 0.0), 40.0),
+        world,
+        objects: vec![object],
+        instances,
+        seed: 11,
+        ..Default::default()
+    });
+    check("instances", img);
+}
+
+#[test]
+fn constant_medium() {
+    let world = vec![
+        ground(),
+        EnumPrimitivePod::new_constant_medium_box(
+            vec3(-1.6, 0.0, -0.5),
+            vec3(-0.4, 1.2, 0.5),
+            2.0,
+            EnumMaterialPod::new_isotropic(vec3(0.1, 0.1, 0.1)),
+        ),
+        EnumPrimitivePod::new_constant_medium_sphere(
+            vec3(0.9, 0.6, 0.0),
+            0.6,
+            1.5,
+            EnumMaterialPod::new_isotropic(vec3(0.9, 0.9, 0.9)),
+        ),
+    ];
+    let img = render(&GoldenScene {
+        camera: camera(vec3(0.0, 1.5, 5.0), vec3(0.0, 0.6, 0.0), 40.0),
+        world,
+        seed: 12,
+        ..Default::default()
+    });
+    check("constant_medium", img);
+}
+
+#[test]
+fn textures() {
+    let mut texels: Vec<Vec4> = perlin::texels(&mut StdRng::seed_from_u64(0))
+        .into_iter()
+        .map(Vec4::from)
+        .collect();
+    // 4 x 2 texels: red and blue halves of longitude, lighter on the top row
+    let image_texel = texels.len() as u32;
+    texels.extend((0..2).flat_map(|j| {
+        (0..4).map(move |i| {
+            let light = if j == 0 { 0.4 } else { 0.0 };
+            if i < 2 {
+                vec4(0.6 + light, light, light, 1.0)
+            } else {
+                vec4(light, light, 0.6 + light, 1.0)
+            }
+        })
+    }));
+
+    let textures = vec![
+        TexturePod::new_checker(vec3(0.2, 0.3, 0.1), vec3(0.9, 0.9, 0.9), 0.5),
+        TexturePod::new_marble(vec3(1.0, 1.0, 1.0), 4.0, 0),
+        TexturePod::new_image(4, 2, image_texel),
+        TexturePod::new_solid(vec3(0.9, 0.6, 0.2)),
+    ];
+    let white = EnumMaterialPod::new_lambertian(vec3(1.0, 1.0, 1.0));
+    let world = vec![
+        EnumPrimitivePod::new_sphere(vec3(0.0, -1000.0, 0.0), 1000.0, white.with_texture(0)),
+        EnumPrimitivePod::new_sphere(vec3(-1.3, 0.6, 0.0), 0.6, white.with_texture(1)),
+        EnumPrimitivePod::new_sphere(vec3(0.0, 0.6, 0.0), 0.6, white.with_texture(2)),
+        EnumPrimitivePod::new_sphere(
+            vec3(1.3, 0.6, 0.0),
+            0.6,
+            EnumMaterialPod::new_metal(vec3(1.0, 1.0, 1.0), 0.1).with_texture(3),
+        ),
+    ];
+
+    check(
+        "textures",
+        render(&GoldenScene {
+            camera: camera(vec3(0.0, 1.5, 5.0), vec3(0.0, 0.6, 0.0), 40.0),
+            world,
+            textures,
+            texels,
+            seed: 13,
+            ..Default::default()
+        }),
+    );
+}
+
+#[test]
+fn instanced_textures() {
+    let textures = vec![TexturePod::new_checker(
+        vec3(0.2, 0.3, 0.1),
+        vec3(0.9, 0.9, 0.9),
+        0.25,
+    )];
+    let checker = EnumMaterialPod::new_lambertian(vec3(1.0, 1.0, 1.0)).with_texture(0);
+    let world = vec![
+        ground(),
+        EnumPrimitivePod::new_sphere(Vec3::ZERO, 0.6, checker),
+    ];
+    let object = 1..world.len();
+    // The same pattern on both, although they are not apart by a whole number of cubes
+    let instances = vec![
+        InstancePod::new(Mat4::from_translation(vec3(-0.7, 0.6, 0.0)), 0, None),
+        InstancePod::new(Mat4::from_translation(vec3(0.73, 0.6, 0.1)), 0, None),
+    ];
+
+    check(
+        "instanced_textures",
+        render(&GoldenScene {
+            camera: camera(vec3(0.0, 1.5, 5.0), vec3(0.0, 0.6, 0.0), 40.0),
+            world,
+            objects: vec![object],
+            instances,
+            textures,
+            seed: 14,
+            ..Default::default()
+        }),
+    );
+}
@@ -0,0 +1,67 @@
+use rand::prelude::*;
+use rukako_shader::{
+    background::EnumBackground,
+    bvh::BVHNode,
+    camera::Camera,
+    instance::Instance,
+    pod::bvh::BVHNodePod,
+    primitive::EnumPrimitive,
+    texture::{EnumTexture, Textures},
+    triangle::Vertex,
+    world::World,
+};
+use spirv_std::glam::Vec4;
+
+use crate::scene::Scene;
+
+/// Renders with the shader code running natively, see `rukako_shader::cpu::render`.
+/// Returns the same accumulation buffer `main_cs` writes, flattened to RGBA floats.
+pub fn render(
+    width: usize,
+    height: usize,
+    n_samples: usize,
+    scene: Scene,
+    bvh: Vec<BVHNodePod>,
+    lights: Vec<u32>,
+    rng: &mut impl Rng,
+) -> Vec<f32> {
+    let camera = scene.camera.to_pod(width as f32 / height as f32);
+
+    let primitives: Vec<EnumPrimitive> = scene.world.into_iter().map(Into::into).collect();
+    let vertices: Vec<Vertex> = scene.vertices.into_iter().map(Into::into).collect();
+    let instances: Vec<Instance> = scene.instances.into_iter().map(Into::into).collect();
+    let textures: Vec<EnumTexture> = scene.textures.into_iter().map(Into::into).collect();
+    let texels: Vec<Vec4> = scene.texels.into_iter().map(Vec4::from).collect();
+    let world = World {
+        primitives: &primitives,
+        vertices: &vertices,
+        instances: &instances,
+        textures: Textures {
+            records: &textures,
+            texels: &texels,
+        },
+        lights: &lights,
+        n_lights: lights.len() as u32,
+    };
+    let bvh: Vec<BVHNode> = bvh.into_iter().map(Into::into).collect();
+    let camera: Camera = camera.into();
+    let background: EnumBackground = scene.background.into();
+
+    let n_tiles = rukako_shader::cpu::tile_count(width, height);
+    let out = rukako_shader::cpu::render(
+        width,
+        height,
+        n_samples,
+        &world,
+        &bvh,
+        &camera,
+        &background,
+        rng,
+        |i| eprint!("\rTiles: {} / {} ", i, n_tiles),
+    );
+    eprint!("\nDone");
+
+    out.into_iter()
+        .flat_map(|v| <[f32; 4]>::from(v).to_vec())
+        .collect()
+}
@@ -0,0 +1,495 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use ::gltf::{buffer, camera::Projection, material::AlphaMode, mesh::Mode, Document, Node};
+use anyhow::{bail, Context};
+use rukako_shader::pod::{BackgroundPod, EnumMaterialPod, EnumPrimitivePod, VertexPod};
+use spirv_std::glam::{vec3, Mat4, Vec3};
+
+use crate::scene::{add_mesh, transform_mesh, CameraDesc, Scene};
+
+/// Extensions whose parameters are read by `convert_material`
+const SUPPORTED_EXTENSIONS: &[&str] = &["KHR_materials_ior", "KHR_materials_transmission"];
+
+/// Vertical field of view of the camera framing a file without one
+const DEFAULT_VFOV: f32 = 40.0;
+
+/// Appends the meshes of the default scene of a `.gltf` or `.glb` file to the buffers of a
+/// scene and returns its perspective cameras. Materials follow the metallic-roughness
+/// model of the file unless `material` overrides them. Features the renderer has no
+/// equivalent for are reported once each on stderr
+pub fn load(
+    path: &Path,
+    transform: Mat4,
+    material: Option<EnumMaterialPod>,
+    world: &mut Vec<EnumPrimitivePod>,
+    vertices: &mut Vec<VertexPod>,
+) -> anyhow::Result<Vec<CameraDesc>> {
+    let (document, buffers) = import_buffers(path)?;
+
+    let mut importer = Importer {
+        document: &document,
+        buffers: &buffers,
+        material,
+        world,
+        vertices,
+        cameras: Vec::new(),
+        warnings: BTreeSet::new(),
+    };
+    importer.import(transform)?;
+
+    for warning in &importer.warnings {
+        eprintln!("{}: {}", path.display(), warning);
+    }
+    Ok(importer.cameras)
+}
+
+/// Reads a glTF file and its buffers like `gltf::import`, but leaves its images alone:
+/// textures are ignored, and a missing or undecodable image must not fail the import.
+/// Only resolves relative paths, `file:` and `data:` URIs
+fn import_buffers(path: &Path) -> anyhow::Result<(Document, Vec<buffer::Data>)> {
+    let ::gltf::Gltf {
+        document, mut blob, ..
+    } = ::gltf::Gltf::open(path)?;
+    let base = path.parent().unwrap_or_else(|| Path::new("./"));
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let mut data = match buffer.source() {
+            buffer::Source::Bin => blob.take().context("missing binary chunk")?,
+            buffer::Source::Uri(uri) => {
+                read_uri(base, uri).with_context(|| format!("failed to read {}", uri))?
+            }
+        };
+        if data.len() < buffer.length() {
+            bail!(
+                "buffer {} has {} bytes, expected {}",
+                buffer.index(),
+                data.len(),
+                buffer.length()
+            );
+        }
+        // Accessors may read up to the next multiple of 4
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        buffers.push(buffer::Data(data));
+    }
+    Ok((document, buffers))
+}
+
+fn read_uri(base: &Path, uri: &str) -> anyhow::Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let encoded = data.split(";base64,").nth(1).unwrap_or(data);
+        Ok(base64::decode(encoded)?)
+    } else if let Some(path) = uri
+        .strip_prefix("file://")
+        .or_else(|| uri.strip_prefix("file:"))
+    {
+        Ok(fs::read(path)?)
+    } else if uri.contains(':') {
+        bail!("unsupported URI scheme")
+    } else {
+        Ok(fs::read(base.join(uri))?)
+    }
+}
+
+/// Renders a glTF file on its own from its first perspective camera, or from a camera
+/// framing every mesh when it has none
+pub fn load_scene(path: &Path) -> anyhow::Result<Scene> {
+    let (mut world, mut vertices) = (Vec::new(), Vec::new());
+    let cameras = load(path, Mat4::IDENTITY, None, &mut world, &mut vertices)?;
+    if world.is_empty() {
+        bail!("scene has no triangles");
+    }
+
+    let camera = match cameras.into_iter().next() {
+        Some(camera) => camera,
+        None => framing_camera(&vertices),
+    };
+
+    Ok(Scene {
+        camera,
+        world,
+        objects: Vec::new(),
+        instances: Vec::new(),
+        vertices,
+        background: BackgroundPod::new_sky(),
+        textures: Vec::new(),
+        texels: Vec::new(),
+        seed: None,
+    })
+}
+
+/// Looks down the -z axis at the bounding sphere of the vertices
+fn framing_camera(vertices: &[VertexPod]) -> CameraDesc {
+    let (minimum, maximum) = vertices.iter().fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(minimum, maximum), vertex| {
+            (
+                minimum.min(vertex.position()),
+                maximum.max(vertex.position()),
+            )
+        },
+    );
+    let center = (minimum + maximum) / 2.0;
+    let radius = (maximum - minimum).length() / 2.0;
+    let distance = radius / (DEFAULT_VFOV.to_radians() / 2.0).sin();
+
+    CameraDesc {
+        look_from: (center + vec3(0.0, 0.0, distance)).into(),
+        look_at: center.into(),
+        vup: [0.0, 1.0, 0.0],
+        vfov: DEFAULT_VFOV,
+        aperture: 0.0,
+        focus_dist: 1.0,
+        time0: 0.0,
+        time1: 1.0,
+    }
+}
+
+struct Importer<'a> {
+    document: &'a Document,
+    buffers: &'a [buffer::Data],
+    material: Option<EnumMaterialPod>,
+    world: &'a mut Vec<EnumPrimitivePod>,
+    vertices: &'a mut Vec<VertexPod>,
+    cameras: Vec<CameraDesc>,
+    warnings: BTreeSet<String>,
+}
+
+impl<'a> Importer<'a> {
+    fn warn(&mut self, warning: impl Into<String>) {
+        self.warnings.insert(warning.into());
+    }
+
+    fn import(&mut self, transform: Mat4) -> anyhow::Result<()> {
+        for extension in self.document.extensions_used() {
+            if !SUPPORTED_EXTENSIONS.contains(&extension) {
+                self.warn(format!("extension {} is ignored", extension));
+            }
+        }
+        if self.document.animations().next().is_some() {
+            self.warn("animations are ignored");
+        }
+        if self.document.skins().next().is_some() {
+            self.warn("skins are ignored, meshes are rendered in their bind pose");
+        }
+
+        let scene = match self
+            .document
+            .default_scene()
+            .or_else(|| self.document.scenes().next())
+        {
+            Some(scene) => scene,
+            None => bail!("file has no scene"),
+        };
+        for node in scene.nodes() {
+            self.node(&node, transform)?;
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: &Node, parent: Mat4) -> anyhow::Result<()> {
+        let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.primitive(&primitive, transform)
+                    .with_context(|| format!("mesh #{}", mesh.index()))?;
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            match camera.projection() {
+                Projection::Perspective(perspective) => {
+                    let look_from = transform.transform_point3(Vec3::ZERO);
+                    let forward = transform.transform_vector3(-Vec3::Z).normalize();
+                    self.cameras.push(CameraDesc {
+                        look_from: look_from.into(),
+                        look_at: (look_from + forward).into(),
+                        vup: transform.transform_vector3(Vec3::Y).normalize().into(),
+                        vfov: perspective.yfov().to_degrees(),
+                        aperture: 0.0,
+                        focus_dist: 1.0,
+                        time0: 0.0,
+                        time1: 1.0,
+                    });
+                }
+                Projection::Orthographic(_) => self.warn("orthographic cameras are ignored"),
+            }
+        }
+
+        for child in node.children() {
+            self.node(&child, transform)?;
+        }
+        Ok(())
+    }
+
+    fn primitive(&mut self, primitive: &::gltf::Primitive, transform: Mat4) -> anyhow::Result<()> {
+        let buffers = self.buffers;
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        let mut positions: Vec<[f32; 3]> = match reader.read_positions() {
+            Some(positions) => positions.collect(),
+            None => bail!("primitive has no positions"),
+        };
+        let mut normals: Vec<[f32; 3]> = match reader.read_normals() {
+            Some(normals) => normals.collect(),
+            None => Vec::new(),
+        };
+        transform_mesh(transform, &mut positions, &mut normals);
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let indices: Vec<[u32; 3]> = match primitive.mode() {
+            Mode::Triangles => indices
+                .chunks_exact(3)
+                .map(|i| [i[0], i[1], i[2]])
+                .collect(),
+            // Every other triangle of a strip is flipped to keep the winding
+            Mode::TriangleStrip => indices
+                .windows(3)
+                .enumerate()
+                .map(|(k, i)| {
+                    if k % 2 == 0 {
+                        [i[0], i[1], i[2]]
+                    } else {
+                        [i[1], i[0], i[2]]
+                    }
+                })
+                .collect(),
+            Mode::TriangleFan => indices
+                .iter()
+                .skip(1)
+                .zip(indices.iter().skip(2))
+                .map(|(&b, &c)| [indices[0], b, c])
+                .collect(),
+            mode => {
+                self.warn(format!("{:?} primitives are ignored", mode));
+                return Ok(());
+            }
+        };
+
+        let material = match self.material {
+            Some(material) => material,
+            None => self.convert_material(&primitive.material()),
+        };
+
+        add_mesh(
+            &positions,
+            &normals,
+            &indices,
+            material,
+            self.world,
+            self.vertices,
+        )
+    }
+
+    /// Maps a metallic-roughness material onto the closest material of the renderer:
+    ///
+    /// - an emissive factor gives a diffuse light,
+    /// - a mostly transmissive material gives a dielectric of index `ior`,
+    /// - a mostly metallic material gives a metal of the base colour, as fuzzy as it is
+    ///   rough,
+    /// - anything else is lambertian with the base colour as albedo.
+    fn convert_material(&mut self, material: &::gltf::Material) -> EnumMaterialPod {
+        let pbr = material.pbr_metallic_roughness();
+        if pbr.base_color_texture().is_some()
+            || pbr.metallic_roughness_texture().is_some()
+            || material.normal_texture().is_some()
+            || material.occlusion_texture().is_some()
+            || material.emissive_texture().is_some()
+        {
+            self.warn("textures are ignored");
+        }
+        if material.alpha_mode() != AlphaMode::Opaque {
+            self.warn("alpha modes are ignored, every material is opaque");
+        }
+
+        let emissive = Vec3::from(material.emissive_factor());
+        if emissive.max_element() > 0.0 {
+            return EnumMaterialPod::new_diffuse_light(emissive);
+        }
+
+        let transmission = material
+            .transmission()
+            .map_or(0.0, |transmission| transmission.transmission_factor());
+        if transmission >= 0.5 {
+            return EnumMaterialPod::new_dielectric(material.ior().unwrap_or(1.5));
+        }
+
+        let [r, g, b, _] = pbr.base_color_factor();
+        let base_color = vec3(r, g, b);
+        if pbr.metallic_factor() >= 0.5 {
+            EnumMaterialPod::new_metal(base_color, pbr.roughness_factor())
+        } else {
+            EnumMaterialPod::new_lambertian(base_color)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::bytes_of;
+
+    use super::*;
+    use crate::scene::fixture_dir;
+
+    /// A triangle in the xy plane under a translated parent with a scaled child, and a
+    /// camera looking at it. `extra` is spliced into the top level object
+    fn write_gltf(name: &str, material: &str, extra: &str) -> std::path::PathBuf {
+        let dir = fixture_dir(&format!("gltf-{}", name));
+
+        let positions: [f32; 9] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let bytes: Vec<u8> = positions.iter().flat_map(|p| p.to_le_bytes()).collect();
+        fs::write(dir.join("triangle.bin"), &bytes).unwrap();
+
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "scene": 0,
+                "scenes": [{{"nodes": [0, 2]}}],
+                "nodes": [
+                    {{"translation": [0, 0, -5], "children": [1]}},
+                    {{"scale": [2, 2, 2], "mesh": 0}},
+                    {{"translation": [0, 1, 3], "camera": 0}}
+                ],
+                "cameras": [{{"type": "perspective", "perspective": {{"yfov": 0.5, "znear": 0.1}}}}],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "material": 0}}]}}],
+                "materials": [{}],
+                "accessors": [{{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0, 0, 0], "max": [1, 1, 0]
+                }}],
+                "bufferViews": [{{"buffer": 0, "byteLength": 36}}],
+                "buffers": [{{"uri": "triangle.bin", "byteLength": 36}}]
+                {}
+            }}"#,
+            material, extra
+        );
+        let path = dir.join("triangle.gltf");
+        fs::write(&path, json).unwrap();
+        path
+    }
+
+    fn import(path: &Path) -> (Vec<EnumPrimitivePod>, Vec<VertexPod>, Vec<CameraDesc>) {
+        let (mut triangles, mut vertices) = (Vec::new(), Vec::new());
+        let cameras = load(path, Mat4::IDENTITY, None, &mut triangles, &mut vertices).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        (triangles, vertices, cameras)
+    }
+
+    #[test]
+    fn node_transforms_and_cameras() {
+        let path = write_gltf(
+            "nodes",
+            r#"{"pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0}}"#,
+            "",
+        );
+        let (triangles, vertices, cameras) = import(&path);
+
+        assert_eq!(triangles.len(), 1);
+        let expected = EnumPrimitivePod::new_triangle(
+            [0, 1, 2],
+            EnumMaterialPod::new_lambertian(vec3(1.0, 0.0, 0.0)),
+        );
+        assert_eq!(bytes_of(&triangles[0]), bytes_of(&expected));
+
+        // Scaled by the child, then moved by the parent
+        assert_eq!(vertices[1].position(), vec3(2.0, 0.0, -5.0));
+        assert_eq!(vertices[2].position(), vec3(0.0, 2.0, -5.0));
+
+        assert_eq!(cameras.len(), 1);
+        assert_eq!(cameras[0].look_from, [0.0, 1.0, 3.0]);
+        assert_eq!(cameras[0].look_at, [0.0, 1.0, 2.0]);
+        assert_eq!(cameras[0].vup, [0.0, 1.0, 0.0]);
+        assert!((cameras[0].vfov - 0.5f32.to_degrees()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn metallic_roughness_mapping() {
+        let cases = vec![
+            (
+                r#"{"pbrMetallicRoughness": {"baseColorFactor": [0.5, 0.5, 0.5, 1], "metallicFactor": 1, "roughnessFactor": 0.25}}"#,
+                EnumMaterialPod::new_metal(vec3(0.5, 0.5, 0.5), 0.25),
+            ),
+            (
+                r#"{"emissiveFactor": [1, 0.5, 0]}"#,
+                EnumMaterialPod::new_diffuse_light(vec3(1.0, 0.5, 0.0)),
+            ),
+            (
+                r#"{"extensions": {"KHR_materials_transmission": {"transmissionFactor": 1}, "KHR_materials_ior": {"ior": 1.33}}}"#,
+                EnumMaterialPod::new_dielectric(1.33),
+            ),
+        ];
+        for (i, (material, expected)) in cases.into_iter().enumerate() {
+            let path = write_gltf(&format!("material{}", i), material, "");
+            let (triangles, _, _) = import(&path);
+            assert_eq!(
+                bytes_of(&triangles[0]),
+                bytes_of(&EnumPrimitivePod::new_triangle([0, 1, 2], expected)),
+                "{}",
+                material
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_features_are_reported() {
+        let path = write_gltf(
+            "warnings",
+            r#"{"alphaMode": "BLEND"}"#,
+            r#", "extensionsUsed": ["KHR_materials_clearcoat", "KHR_materials_ior"]"#,
+        );
+        let (document, buffers) = import_buffers(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let (mut triangles, mut vertices) = (Vec::new(), Vec::new());
+        let mut importer = Importer {
+            document: &document,
+            buffers: &buffers,
+            material: None,
+            world: &mut triangles,
+            vertices: &mut vertices,
+            cameras: Vec::new(),
+            warnings: BTreeSet::new(),
+        };
+        importer.import(Mat4::IDENTITY).unwrap();
+
+        let warnings: Vec<_> = importer.warnings.into_iter().collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "alpha modes are ignored, every material is opaque",
+                "extension KHR_materials_clearcoat is ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn images_are_not_decoded() {
+        let path = write_gltf(
+            "images",
+            r#"{"pbrMetallicRoughness": {"baseColorTexture": {"index": 0}}}"#,
+            r#", "textures": [{"source": 0}], "images": [{"uri": "missing.webp"}]"#,
+        );
+        let (triangles, _, _) = import(&path);
+        assert_eq!(triangles.len(), 1);
+    }
+
+    #[test]
+    fn remote_buffers_are_rejected() {
+        let path = write_gltf("remote", "{}", "");
+        let json = fs::read_to_string(&path)
+            .unwrap()
+            .replace("triangle.bin", "https://example.com/triangle.bin");
+        fs::write(&path, json).unwrap();
+
+        let (mut triangles, mut vertices) = (Vec::new(), Vec::new());
+        let result = load(&path, Mat4::IDENTITY, None, &mut triangles, &mut vertices);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -1,22 +1,60 @@
-use std::{borrow::Cow, fs::File, num::NonZeroU64, path::Path};
+use std::{borrow::Cow, fs::File, io::BufWriter, num::NonZeroU64, path::Path, time::Instant};
 
-use image::{png::PngEncoder, ImageEncoder};
+use anyhow::Context;
+use opts::{Backend, Bvh, Opt, Scene};
+use output::{LinearImage, OutputFormat};
 use rand::prelude::*;
 use rukako_shader::{
-    pod::{bvh::create_bvh, EnumMaterialPod, SpherePod},
+    pod::{
+        bvh::{create_instanced_bvh, sah_cost, BVHBuilder, BVHNodePod},
+        bvh_stats::{self, InstancedBVHStats},
+        light::light_list,
+        BackgroundPod, EnumMaterialPod, EnumPrimitivePod, InstancePod, VertexPod,
+    },
     ShaderConstants, NUM_THREADS_X, NUM_THREADS_Y,
 };
 use spirv_std::glam::vec3;
+use structopt::StructOpt;
+use tonemap::Tonemapper;
 use wgpu::util::DeviceExt;
 
+mod cpu;
+mod gltf;
+mod obj;
+mod opts;
+mod output;
+mod scene;
+mod tonemap;
+
 const SHADER: &[u8] = include_bytes!(env!("rukako_shader.spv"));
 
-fn random_scene() -> Vec<SpherePod> {
-    let mut rng = StdRng::from_entropy();
+fn weekend_scene(rng: &mut impl Rng) -> scene::Scene {
+    scene::Scene {
+        camera: scene::CameraDesc {
+            look_from: [13.0, 2.0, 3.0],
+            look_at: [0.0, 0.0, 0.0],
+            vup: [0.0, 1.0, 0.0],
+            vfov: 20.0,
+            aperture: 0.1,
+            focus_dist: 10.0,
+            time0: 0.0,
+            time1: 1.0,
+        },
+        world: random_scene(rng),
+        objects: Vec::new(),
+        instances: Vec::new(),
+        vertices: Vec::new(),
+        background: BackgroundPod::new_sky(),
+        textures: Vec::new(),
+        texels: Vec::new(),
+        seed: None,
+    }
+}
 
+fn random_scene(rng: &mut impl Rng) -> Vec<EnumPrimitivePod> {
     let mut world = Vec::new();
 
-    world.push(SpherePod::new(
+    world.push(EnumPrimitivePod::new_sphere(
         vec3(0.0, -1000.0, 0.0),
         1000.0,
         EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5)),
@@ -38,7 +76,7 @@ fn random_scene() -> Vec<SpherePod> {
                         let albedo = vec3(rng.gen(), rng.gen(), rng.gen())
                             * vec3(rng.gen(), rng.gen(), rng.gen());
 
-                        world.push(SpherePod::new(
+                        world.push(EnumPrimitivePod::new_sphere(
                             center,
                             0.3,
                             EnumMaterialPod::new_lambertian(albedo),
@@ -52,13 +90,13 @@ fn random_scene() -> Vec<SpherePod> {
                         );
                         let fuzz = rng.gen_range(0.0..0.5);
 
-                        world.push(SpherePod::new(
+                        world.push(EnumPrimitivePod::new_sphere(
                             center,
                             0.2,
                             EnumMaterialPod::new_metal(albedo, fuzz),
                         ));
                     }
-                    _ => world.push(SpherePod::new(
+                    _ => world.push(EnumPrimitivePod::new_sphere(
                         center,
                         0.2,
                         EnumMaterialPod::new_dielectric(1.5),
@@ -68,17 +106,17 @@ fn random_scene() -> Vec<SpherePod> {
         }
     }
 
-    world.push(SpherePod::new(
+    world.push(EnumPrimitivePod::new_sphere(
         vec3(0.0, 1.0, 0.0),
         1.0,
         EnumMaterialPod::new_dielectric(1.5),
     ));
-    world.push(SpherePod::new(
+    world.push(EnumPrimitivePod::new_sphere(
         vec3(-4.0, 1.0, 0.0),
         1.0,
         EnumMaterialPod::new_lambertian(vec3(0.4, 0.2, 0.1)),
     ));
-    world.push(SpherePod::new(
+    world.push(EnumPrimitivePod::new_sphere(
         vec3(4.0, 1.0, 0.0),
         1.0,
         EnumMaterialPod::new_metal(vec3(0.7, 0.6, 0.5), 0.0),
@@ -87,12 +125,45 @@ fn random_scene() -> Vec<SpherePod> {
     world
 }
 
+/// Builds the BVH of `scene` and reports how long it took and how good it is.
+/// The primitives and instances of `scene` are reordered to match the leaves.
+fn build_bvh(
+    scene: &mut scene::Scene,
+    builder: BVHBuilder,
+    max_leaf_size: usize,
+    rng: &mut impl Rng,
+) -> anyhow::Result<Vec<BVHNodePod>> {
+    let start = Instant::now();
+    let bvh = create_instanced_bvh(
+        &mut scene.world,
+        &scene.objects,
+        &mut scene.instances,
+        &scene.vertices,
+        scene.camera.time0,
+        scene.camera.time1,
+        builder,
+        max_leaf_size,
+        rng,
+    )?;
+    eprintln!(
+        "BVH: {} nodes built with {:?} in {:.2?}, SAH cost {:.3}",
+        bvh.len(),
+        builder,
+        start.elapsed(),
+        sah_cost(&bvh, &scene.instances)
+    );
+    Ok(bvh)
+}
+
 async fn run(
     width: usize,
     height: usize,
     n_samples: usize,
-    output_png_file_name: impl AsRef<Path>,
-) {
+    mut scene: scene::Scene,
+    bvh: Vec<BVHNodePod>,
+    mut lights: Vec<u32>,
+    rng: &mut impl Rng,
+) -> anyhow::Result<Vec<f32>> {
     let instance = wgpu::Instance::new(wgpu::BackendBit::all());
     let adapter = instance
         .request_adapter(&wgpu::RequestAdapterOptions::default())
@@ -122,9 +193,28 @@ async fn run(
         flags: wgpu::ShaderFlags::default(),
     });
 
-    let mut rng = StdRng::from_entropy();
-    let mut world = random_scene();
-    let bvh = create_bvh(&mut world, 0.0, 1.0, &mut rng);
+    let camera = scene.camera.to_pod(width as f32 / height as f32);
+
+    // Storage buffers can't be empty
+    if scene.texels.is_empty() {
+        scene.texels.push([0.0; 4]);
+    }
+    if scene.world.is_empty() {
+        scene.world.push(bytemuck::Zeroable::zeroed());
+    }
+    if scene.vertices.is_empty() {
+        scene.vertices.push(VertexPod::default());
+    }
+    if scene.instances.is_empty() {
+        scene.instances.push(bytemuck::Zeroable::zeroed());
+    }
+    if scene.textures.is_empty() {
+        scene.textures.push(bytemuck::Zeroable::zeroed());
+    }
+    let n_lights = lights.len() as u32;
+    if lights.is_empty() {
+        lights.push(0);
+    }
 
     let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: None,
@@ -161,6 +251,76 @@ async fn run(
                     ty: wgpu::BufferBindingType::Storage { read_only: false },
                 },
             },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                count: None,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                    ty: wgpu::BufferBindingType::Uniform,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                count: None,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                    ty: wgpu::BufferBindingType::Uniform,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                count: None,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(NonZeroU64::new(1).unwrap()),
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                count: None,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(NonZeroU64::new(1).unwrap()),
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                count: None,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(NonZeroU64::new(1).unwrap()),
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 8,
+                count: None,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(NonZeroU64::new(1).unwrap()),
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 9,
+                count: None,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(NonZeroU64::new(1).unwrap()),
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                },
+            },
         ],
     });
 
@@ -200,7 +360,7 @@ async fn run(
 
     let world_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("world"),
-        contents: bytemuck::cast_slice(world.as_slice()),
+        contents: bytemuck::cast_slice(scene.world.as_slice()),
         usage: wgpu::BufferUsage::STORAGE
             // | wgpu::BufferUsage::COPY_DST
             // | wgpu::BufferUsage::COPY_SRC,
@@ -214,6 +374,48 @@ async fn run(
             // | wgpu::BufferUsage::COPY_SRC,
     });
 
+    let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("camera"),
+        contents: bytemuck::bytes_of(&camera),
+        usage: wgpu::BufferUsage::UNIFORM,
+    });
+
+    let background_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("background"),
+        contents: bytemuck::bytes_of(&scene.background),
+        usage: wgpu::BufferUsage::UNIFORM,
+    });
+
+    let texel_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("texels"),
+        contents: bytemuck::cast_slice(scene.texels.as_slice()),
+        usage: wgpu::BufferUsage::STORAGE,
+    });
+
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("vertices"),
+        contents: bytemuck::cast_slice(scene.vertices.as_slice()),
+        usage: wgpu::BufferUsage::STORAGE,
+    });
+
+    let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("instances"),
+        contents: bytemuck::cast_slice(scene.instances.as_slice()),
+        usage: wgpu::BufferUsage::STORAGE,
+    });
+
+    let texture_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("textures"),
+        contents: bytemuck::cast_slice(scene.textures.as_slice()),
+        usage: wgpu::BufferUsage::STORAGE,
+    });
+
+    let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("lights"),
+        contents: bytemuck::cast_slice(lights.as_slice()),
+        usage: wgpu::BufferUsage::STORAGE,
+    });
+
     let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: None,
         layout: &bind_group_layout,
@@ -230,6 +432,34 @@ async fn run(
                 binding: 2,
                 resource: storage_buffer.as_entire_binding(),
             },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: background_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: texel_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: vertex_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: instance_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: texture_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: light_buffer.as_entire_binding(),
+            },
         ],
     });
 
@@ -237,6 +467,7 @@ async fn run(
         width: width as u32,
         height: height as u32,
         seed: rng.gen(),
+        n_lights,
     };
 
     for i in 0..n_samples {
@@ -279,34 +510,101 @@ async fn run(
 
     device.poll(wgpu::Maintain::Wait);
 
-    if let Ok(()) = buffer_future.await {
-        let padded_buffer = buffer_slice.get_mapped_range();
+    buffer_future.await?;
 
-        let png_encoder = PngEncoder::new(File::create(output_png_file_name).unwrap());
+    let padded_buffer = buffer_slice.get_mapped_range();
+    let v4: Vec<f32> = bytemuck::cast_slice(&padded_buffer[..]).to_vec();
+    drop(padded_buffer);
 
-        let v4: &[f32] = bytemuck::cast_slice(&padded_buffer[..]);
+    readback_buffer.unmap();
 
-        let scale = 1.0 / n_samples as f32;
-
-        let rgba: Vec<u8> = v4
-            .iter()
-            .map(|f| (256.0 * (f * scale).sqrt().clamp(0.0, 0.999)) as u8)
-            .collect();
-        png_encoder
-            .write_image(
-                rgba.as_slice(),
-                width as u32,
-                height as u32,
-                image::ColorType::Rgba8,
-            )
-            .unwrap();
-        drop(padded_buffer);
+    Ok(v4)
+}
 
-        readback_buffer.unmap();
+/// Writes the boxes of the BVH as an OBJ wireframe or the tree as a Graphviz graph,
+/// depending on the extension of `path`
+fn dump_bvh(bvh: &[BVHNodePod], instances: &[InstancePod], path: &Path) -> anyhow::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("obj") => bvh_stats::write_obj(bvh, instances, file)?,
+        _ => bvh_stats::write_dot(bvh, instances, file)?,
     }
+    Ok(())
 }
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     env_logger::init();
-    pollster::block_on(run(1200, 800, 500, "out.png"));
+    let opt = Opt::from_args();
+
+    let scene = match &opt.scene_file {
+        Some(path) => Some(scene::load(path)?),
+        None => None,
+    };
+
+    let seed = opt
+        .seed
+        .or_else(|| scene.as_ref().and_then(|scene| scene.seed))
+        .unwrap_or_else(random);
+    eprintln!("Seed: {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut scene = match scene {
+        Some(scene) => scene,
+        None => match opt.scene {
+            Scene::Weekend => weekend_scene(&mut rng),
+        },
+    };
+
+    let builder = match opt.bvh {
+        Bvh::Sah => BVHBuilder::Sah,
+        Bvh::Median => BVHBuilder::RandomMedian,
+    };
+
+    let bvh = build_bvh(&mut scene, builder, opt.bvh_leaf_size, &mut rng)?;
+    let lights = light_list(&scene.world, &scene.objects);
+    if opt.bvh_stats {
+        eprintln!("{}", InstancedBVHStats::new(&bvh, &scene.instances));
+    }
+    if let Some(path) = &opt.bvh_dump {
+        dump_bvh(&bvh, &scene.instances, path)
+            .with_context(|| format!("writing {}", path.display()))?;
+    }
+
+    let v4 = match opt.backend {
+        Backend::Gpu => pollster::block_on(run(
+            opt.width,
+            opt.height,
+            opt.samples,
+            scene,
+            bvh,
+            lights,
+            &mut rng,
+        ))?,
+        Backend::Cpu => cpu::render(
+            opt.width,
+            opt.height,
+            opt.samples,
+            scene,
+            bvh,
+            lights,
+            &mut rng,
+        ),
+    };
+
+    let image = LinearImage::from_accumulation(&v4, opt.width, opt.height, opt.samples);
+    let tonemapper = Tonemapper {
+        operator: opt.tonemap,
+        exposure: opt.exposure,
+        dither: opt.dither,
+    };
+
+    OutputFormat::from_path(&opt.output)
+        .expect("validated by Opt")
+        .write(&image, &tonemapper, seed, &opt.output)?;
+
+    if let Some(png) = &opt.png {
+        OutputFormat::Png.write(&image, &tonemapper, seed, png)?;
+    }
+
+    Ok(())
 }
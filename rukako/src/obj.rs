@@ -0,0 +1,239 @@
+use std::path::Path;
+
+use anyhow::Context;
+use rukako_shader::pod::{EnumMaterialPod, EnumPrimitivePod, VertexPod};
+use spirv_std::glam::{vec3, Mat4, Vec3};
+
+use crate::scene::{add_mesh, transform_mesh};
+
+/// Used for faces without a material, e.g. when the MTL file is missing
+fn default_material() -> EnumMaterialPod {
+    EnumMaterialPod::new_lambertian(vec3(0.8, 0.8, 0.8))
+}
+
+/// Appends the triangles of a Wavefront OBJ file to the buffers of a scene. Faces are
+/// triangulated as fans and meshes without normals are flat shaded. Materials come from
+/// the MTL files of the OBJ unless `material` overrides them
+pub fn load(
+    path: &Path,
+    transform: Mat4,
+    material: Option<EnumMaterialPod>,
+    world: &mut Vec<EnumPrimitivePod>,
+    vertices: &mut Vec<VertexPod>,
+) -> anyhow::Result<()> {
+    let (models, materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?;
+
+    let materials = match (material, materials) {
+        (Some(_), _) => Vec::new(),
+        (None, Ok(materials)) => materials.iter().map(convert_material).collect(),
+        (None, Err(e)) => {
+            eprintln!("{}: ignoring materials: {}", path.display(), e);
+            Vec::new()
+        }
+    };
+
+    for model in &models {
+        let mesh = &model.mesh;
+        let mut positions: Vec<[f32; 3]> = mesh
+            .positions
+            .chunks_exact(3)
+            .map(|p| [p[0], p[1], p[2]])
+            .collect();
+        let mut normals: Vec<[f32; 3]> = mesh
+            .normals
+            .chunks_exact(3)
+            .map(|n| [n[0], n[1], n[2]])
+            .collect();
+        transform_mesh(transform, &mut positions, &mut normals);
+        let indices: Vec<[u32; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|i| [i[0], i[1], i[2]])
+            .collect();
+
+        let material = material.unwrap_or_else(|| {
+            mesh.material_id
+                .and_then(|id| materials.get(id).copied())
+                .unwrap_or_else(default_material)
+        });
+
+        add_mesh(&positions, &normals, &indices, material, world, vertices)
+            .with_context(|| format!("object {:?}", model.name))?;
+    }
+
+    Ok(())
+}
+
+/// Maps an MTL material onto the closest material of the renderer:
+///
+/// - an emissive `Ke` gives a diffuse light,
+/// - a dissolve `d` below 1 or a refracting illumination model gives a dielectric of
+///   index `Ni`,
+/// - a reflecting illumination model or a specular `Ks` brighter than the diffuse `Kd`
+///   gives a metal of albedo `Ks`, with the exponent `Ns` setting the fuzz,
+/// - anything else is lambertian with albedo `Kd`.
+fn convert_material(material: &tobj::Material) -> EnumMaterialPod {
+    let emit = material
+        .unknown_param
+        .get("Ke")
+        .and_then(|s| parse_color(s))
+        .unwrap_or(Vec3::ZERO);
+    if emit.max_element() > 0.0 {
+        return EnumMaterialPod::new_diffuse_light(emit);
+    }
+
+    let illum = material.illumination_model;
+    if material.dissolve < 1.0 || matches!(illum, Some(4) | Some(6) | Some(7) | Some(9)) {
+        let ir = if material.optical_density > 1.0 {
+            material.optical_density
+        } else {
+            1.5
+        };
+        return EnumMaterialPod::new_dielectric(ir);
+    }
+
+    let diffuse = Vec3::from(material.diffuse);
+    let specular = Vec3::from(material.specular);
+    let reflective = matches!(illum, Some(3) | Some(5) | Some(8))
+        || specular.max_element() > diffuse.max_element();
+    if reflective && specular.max_element() > 0.0 {
+        // Maps the Phong exponent to a roughness, as in the Blinn-Phong to Beckmann fit
+        let fuzz = (2.0 / (material.shininess.max(0.0) + 2.0)).sqrt();
+        return EnumMaterialPod::new_metal(specular, fuzz);
+    }
+
+    EnumMaterialPod::new_lambertian(diffuse)
+}
+
+fn parse_color(s: &str) -> Option<Vec3> {
+    let mut values = s.split_whitespace().map(|v| v.parse::<f32>());
+    let r = values.next()?.ok()?;
+    // A single value applies to every channel
+    match (values.next(), values.next()) {
+        (None, None) => Some(vec3(r, r, r)),
+        (Some(g), Some(b)) => Some(vec3(r, g.ok()?, b.ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use bytemuck::bytes_of;
+    use spirv_std::glam::Quat;
+
+    use super::*;
+    use crate::scene::fixture_dir;
+
+    fn assert_material(actual: EnumMaterialPod, expected: EnumMaterialPod) {
+        assert_eq!(bytes_of(&actual), bytes_of(&expected));
+    }
+
+    fn mtl(f: impl FnOnce(&mut tobj::Material)) -> EnumMaterialPod {
+        let mut material = tobj::Material {
+            diffuse: [0.5, 0.25, 0.125],
+            ..Default::default()
+        };
+        f(&mut material);
+        convert_material(&material)
+    }
+
+    #[test]
+    fn mtl_mapping() {
+        assert_material(
+            mtl(|_| {}),
+            EnumMaterialPod::new_lambertian(vec3(0.5, 0.25, 0.125)),
+        );
+        assert_material(
+            mtl(|m| {
+                m.unknown_param
+                    .insert("Ke".to_string(), "4 4 2".to_string());
+            }),
+            EnumMaterialPod::new_diffuse_light(vec3(4.0, 4.0, 2.0)),
+        );
+        assert_material(
+            mtl(|m| {
+                m.dissolve = 0.1;
+                m.optical_density = 1.33;
+            }),
+            EnumMaterialPod::new_dielectric(1.33),
+        );
+        assert_material(
+            mtl(|m| m.illumination_model = Some(7)),
+            EnumMaterialPod::new_dielectric(1.5),
+        );
+        assert_material(
+            mtl(|m| {
+                m.specular = [0.9, 0.9, 0.9];
+                m.shininess = 198.0;
+            }),
+            EnumMaterialPod::new_metal(vec3(0.9, 0.9, 0.9), 0.1),
+        );
+        // A dim highlight on a diffuse surface stays lambertian
+        assert_material(
+            mtl(|m| m.specular = [0.1, 0.1, 0.1]),
+            EnumMaterialPod::new_lambertian(vec3(0.5, 0.25, 0.125)),
+        );
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("1 0.5 0"), Some(vec3(1.0, 0.5, 0.0)));
+        assert_eq!(parse_color("2"), Some(vec3(2.0, 2.0, 2.0)));
+        assert_eq!(parse_color("1 2"), None);
+        assert_eq!(parse_color("red"), None);
+    }
+
+    #[test]
+    fn loads_transformed_quad() {
+        let dir = fixture_dir("obj");
+        fs::write(dir.join("quad.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+        fs::write(
+            dir.join("quad.obj"),
+            "mtllib quad.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vn 0 0 1\n\
+             usemtl red\n\
+             f 1//1 2//1 3//1 4//1\n",
+        )
+        .unwrap();
+
+        let transform = Mat4::from_scale_rotation_translation(
+            vec3(2.0, 1.0, 1.0),
+            Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+            vec3(0.0, 0.0, 5.0),
+        );
+        let (mut triangles, mut vertices) = (Vec::new(), Vec::new());
+        load(
+            &dir.join("quad.obj"),
+            transform,
+            None,
+            &mut triangles,
+            &mut vertices,
+        )
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The quad is split into two triangles sharing its four corners
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(vertices.len(), 4);
+        let red = EnumMaterialPod::new_lambertian(vec3(1.0, 0.0, 0.0));
+        let expected = EnumPrimitivePod::new_triangle([0, 1, 2], red);
+        assert_eq!(bytes_of(&triangles[0]), bytes_of(&expected));
+
+        // Scaled along x, then turned onto the z axis and moved
+        let corner = vertices[1].position();
+        assert!(
+            (corner - vec3(0.0, 0.0, 3.0)).length() < 1e-5,
+            "{:?}",
+            corner
+        );
+        let normal = vertices[1].normal();
+        assert!(
+            (normal - vec3(1.0, 0.0, 0.0)).length() < 1e-5,
+            "{:?}",
+            normal
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+
+use structopt::{clap::arg_enum, StructOpt};
+
+use crate::{output::OutputFormat, tonemap::Operator};
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Scene {
+        Weekend,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Backend {
+        Gpu,
+        Cpu,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Bvh {
+        Sah,
+        Median,
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "rukako", about = "A GPU renderer in Rust")]
+pub struct Opt {
+    /// Width of the output image in pixels
+    #[structopt(long, default_value = "1200", parse(try_from_str = parse_dimension))]
+    pub width: usize,
+
+    /// Height of the output image in pixels
+    #[structopt(long, default_value = "800", parse(try_from_str = parse_dimension))]
+    pub height: usize,
+
+    /// Number of samples per pixel
+    #[structopt(short, long, default_value = "500", parse(try_from_str = parse_positive))]
+    pub samples: usize,
+
+    /// Output file. The format follows the extension: .png is tonemapped, while .exr, .hdr
+    /// and .pfm keep the linear radiance
+    #[structopt(short, long, default_value = "out.png", parse(try_from_os_str = parse_output))]
+    pub output: PathBuf,
+
+    /// Also write a tonemapped PNG, e.g. next to an HDR output
+    #[structopt(long, parse(try_from_os_str = parse_png))]
+    pub png: Option<PathBuf>,
+
+    /// Tonemapping operator for PNG outputs
+    #[structopt(
+        long,
+        default_value = "clamp",
+        possible_values = &Operator::variants(),
+        case_insensitive = true
+    )]
+    pub tonemap: Operator,
+
+    /// Exposure compensation in stops, applied before tonemapping
+    #[structopt(long, default_value = "0", allow_hyphen_values = true)]
+    pub exposure: f32,
+
+    /// Dither before quantising PNG outputs to 8 bits
+    #[structopt(long)]
+    pub dither: bool,
+
+    /// Master seed of the render. Falls back to the seed of the scene file, then to a
+    /// random one. The seed used is printed and recorded in the output PNG
+    #[structopt(long)]
+    pub seed: Option<u64>,
+
+    /// Scene to render
+    #[structopt(
+        long,
+        default_value = "weekend",
+        possible_values = &Scene::variants(),
+        case_insensitive = true
+    )]
+    pub scene: Scene,
+
+    /// Scene description file in RON format. Takes precedence over `--scene`
+    #[structopt(long, parse(from_os_str))]
+    pub scene_file: Option<PathBuf>,
+
+    /// Backend used for rendering
+    #[structopt(
+        long,
+        default_value = "gpu",
+        possible_values = &Backend::variants(),
+        case_insensitive = true
+    )]
+    pub backend: Backend,
+
+    /// BVH builder. `sah` splits by the surface area heuristic, `median` at the median of
+    /// a random axis
+    #[structopt(
+        long,
+        default_value = "sah",
+        possible_values = &Bvh::variants(),
+        case_insensitive = true
+    )]
+    pub bvh: Bvh,
+
+    /// Maximum number of primitives in a BVH leaf
+    #[structopt(long, default_value = "4", parse(try_from_str = parse_positive))]
+    pub bvh_leaf_size: usize,
+
+    /// Print node count, depth, SAH cost and overlap of the BVH
+    #[structopt(long)]
+    pub bvh_stats: bool,
+
+    /// Dump the BVH as an OBJ wireframe (.obj) or a Graphviz graph (.dot)
+    #[structopt(long, parse(try_from_os_str = parse_bvh_dump))]
+    pub bvh_dump: Option<PathBuf>,
+}
+
+fn parse_dimension(s: &str) -> Result<usize, String> {
+    let n: usize = s.parse().map_err(|e| format!("{}", e))?;
+    // The shader divides by `width - 1` and `height - 1`
+    if n < 2 {
+        return Err(format!("must be at least 2, got {}", n));
+    }
+    Ok(n)
+}
+
+fn parse_positive(s: &str) -> Result<usize, String> {
+    let n: usize = s.parse().map_err(|e| format!("{}", e))?;
+    if n == 0 {
+        return Err("must be at least 1".to_string());
+    }
+    Ok(n)
+}
+
+fn parse_output(s: &std::ffi::OsStr) -> Result<PathBuf, std::ffi::OsString> {
+    let path = PathBuf::from(s);
+    match OutputFormat::from_path(&path) {
+        Some(_) => Ok(path),
+        None => Err(format!(
+            "{} has an unsupported extension. Use .png, .exr, .hdr or .pfm",
+            path.display()
+        )
+        .into()),
+    }
+}
+
+fn parse_png(s: &std::ffi::OsStr) -> Result<PathBuf, std::ffi::OsString> {
+    let path = PathBuf::from(s);
+    match OutputFormat::from_path(&path) {
+        Some(OutputFormat::Png) => Ok(path),
+        _ => Err(format!("{} must end with .png", path.display()).into()),
+    }
+}
+
+fn parse_bvh_dump(s: &std::ffi::OsStr) -> Result<PathBuf, std::ffi::OsString> {
+    let path = PathBuf::from(s);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("obj") | Some("dot") => Ok(path),
+        _ => Err(format!("{} must end with .obj or .dot", path.display()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Opt, structopt::clap::Error> {
+        Opt::from_iter_safe(std::iter::once("rukako").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn output_extensions_are_checked() {
+        assert!(parse(&["--output", "out.exr"]).is_ok());
+        assert!(parse(&["--output", "out.jpg"]).is_err());
+    }
+
+    #[test]
+    fn png_must_be_a_png() {
+        let opt = parse(&["--output", "out.exr", "--png", "preview.PNG"]).unwrap();
+        assert_eq!(opt.png, Some(PathBuf::from("preview.PNG")));
+        assert!(parse(&["--output", "out.exr", "--png", "preview.exr"]).is_err());
+        assert!(parse(&["--png", "preview"]).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use exr::{
+    image::{Image, SpecificChannels},
+    math::Vec2,
+    meta::attribute::{AttributeValue, Text},
+    prelude::WritableImage,
+};
+use image::{codecs::hdr::HdrEncoder, Rgb};
+
+use crate::tonemap::Tonemapper;
+
+/// Averaged linear radiance of a render, top row first
+pub struct LinearImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl LinearImage {
+    /// Divides the accumulation buffer written by `main_cs` by the number of samples
+    pub fn from_accumulation(v4: &[f32], width: usize, height: usize, n_samples: usize) -> Self {
+        let scale = 1.0 / n_samples as f32;
+        let pixels = v4
+            .chunks_exact(4)
+            .map(|c| [c[0] * scale, c[1] * scale, c[2] * scale])
+            .collect();
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Tonemapped 8-bit image
+    Png,
+    /// OpenEXR, 32-bit float
+    Exr,
+    /// Radiance RGBE
+    Hdr,
+    /// Portable float map
+    Pfm,
+}
+
+impl OutputFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(OutputFormat::Png),
+            "exr" => Some(OutputFormat::Exr),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            _ => None,
+        }
+    }
+
+    /// Writes `image` to `path`. Only PNG is tonemapped. PNG and EXR record the seed;
+    /// the other formats have nowhere to put it.
+    pub fn write(
+        self,
+        image: &LinearImage,
+        tonemapper: &Tonemapper,
+        seed: u64,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        match self {
+            OutputFormat::Png => write_png(image, tonemapper, seed, path),
+            OutputFormat::Exr => write_exr(image, seed, path),
+            OutputFormat::Hdr => write_hdr(image, path),
+            OutputFormat::Pfm => write_pfm(image, path),
+        }
+        .with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+fn write_png(
+    image: &LinearImage,
+    tonemapper: &Tonemapper,
+    seed: u64,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut png_encoder = png::Encoder::new(file, image.width as u32, image.height as u32);
+    png_encoder.set_color(png::ColorType::RGBA);
+    png_encoder.set_depth(png::BitDepth::Eight);
+
+    let rgba = tonemapper.to_rgba8(image);
+
+    let mut writer = png_encoder.write_header()?;
+    // Record the seed so that the image can be reproduced
+    writer.write_chunk(*b"tEXt", format!("Seed\0{}", seed).as_bytes())?;
+    writer.write_image_data(rgba.as_slice())?;
+
+    Ok(())
+}
+
+fn write_exr(image: &LinearImage, seed: u64, path: &Path) -> anyhow::Result<()> {
+    let width = image.width;
+    let channels = SpecificChannels::rgb(|Vec2(x, y)| {
+        let [r, g, b] = image.pixels[y * width + x];
+        (r, g, b)
+    });
+
+    let mut exr_image = Image::from_channels((image.width, image.height), channels);
+    exr_image.attributes.other.insert(
+        Text::from("seed"),
+        AttributeValue::Text(Text::from(seed.to_string().as_str())),
+    );
+
+    exr_image.write().to_file(path)?;
+    Ok(())
+}
+
+fn write_hdr(image: &LinearImage, path: &Path) -> anyhow::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let pixels: Vec<Rgb<f32>> = image.pixels.iter().map(|p| Rgb(*p)).collect();
+    HdrEncoder::new(file).encode(&pixels, image.width, image.height)?;
+    Ok(())
+}
+
+fn write_pfm(image: &LinearImage, path: &Path) -> anyhow::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    // A negative scale means little-endian
+    write!(file, "PF\n{} {}\n-1.0\n", image.width, image.height)?;
+    // PFM stores the bottom row first
+    for row in image.pixels.chunks_exact(image.width).rev() {
+        for p in row {
+            for c in p {
+                file.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Read},
+        path::PathBuf,
+    };
+
+    use super::*;
+    use crate::scene::fixture_dir;
+
+    const SEED: u64 = 1234567;
+
+    /// 2×3 with distinct pixels, so flips and transposes show. The values survive RGBE
+    fn image() -> LinearImage {
+        LinearImage {
+            width: 2,
+            height: 3,
+            pixels: vec![
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 0.5, 2.0],
+                [0.5, 0.25, 0.125],
+            ],
+        }
+    }
+
+    fn write(format: OutputFormat) -> PathBuf {
+        let path = fixture_dir(&format!("output-{:?}", format))
+            .join(format!("image.{:?}", format).to_lowercase());
+        format
+            .write(&image(), &Tonemapper::default(), SEED, &path)
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let path = write(OutputFormat::Png);
+        let png = image::open(&path).unwrap().into_rgba8();
+        assert_eq!(png.dimensions(), (2, 3));
+        assert_eq!(png.into_raw(), Tonemapper::default().to_rgba8(&image()));
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        let text = format!("tEXtSeed\0{}", SEED);
+        assert!(bytes
+            .windows(text.len())
+            .any(|window| window == text.as_bytes()));
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let path = write(OutputFormat::Exr);
+        let exr = exr::prelude::read_first_rgba_layer_from_file(
+            &path,
+            |resolution, _| vec![[0.0; 3]; resolution.width() * resolution.height()],
+            |pixels, Vec2(x, y), (r, g, b, _): (f32, f32, f32, f32)| pixels[y * 2 + x] = [r, g, b],
+        )
+        .unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(exr.layer_data.size, Vec2(2, 3));
+        assert_eq!(exr.layer_data.channel_data.pixels, image().pixels);
+        // The reader files attributes it does not know under the layer
+        assert_eq!(
+            exr.layer_data.attributes.other.get(&Text::from("seed")),
+            Some(&AttributeValue::Text(Text::from(SEED.to_string().as_str())))
+        );
+    }
+
+    #[test]
+    fn hdr_round_trip() {
+        let path = write(OutputFormat::Hdr);
+        let decoder =
+            image::codecs::hdr::HdrDecoder::new(BufReader::new(File::open(&path).unwrap()))
+                .unwrap();
+        let metadata = decoder.metadata();
+        assert_eq!((metadata.width, metadata.height), (2, 3));
+        let pixels: Vec<[f32; 3]> = decoder
+            .read_image_hdr()
+            .unwrap()
+            .into_iter()
+            .map(|p| p.0)
+            .collect();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(pixels, image().pixels);
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let path = write(OutputFormat::Pfm);
+        let mut reader = BufReader::new(File::open(&path).unwrap());
+        let mut header = Vec::new();
+        for _ in 0..3 {
+            reader.read_until(b'\n', &mut header).unwrap();
+        }
+        // A negative scale means little-endian
+        assert_eq!(header, b"PF\n2 3\n-1.0\n");
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        let values: Vec<f32> = data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        // Bottom row first
+        let expected: Vec<f32> = image()
+            .pixels
+            .chunks_exact(2)
+            .rev()
+            .flatten()
+            .flatten()
+            .copied()
+            .collect();
+        assert_eq!(values, expected);
+    }
+}
@@ -0,0 +1,1012 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::BufReader,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context};
+use image::codecs::hdr::HdrDecoder;
+use rand::prelude::*;
+use rukako_shader::pod::{
+    perlin, BackgroundPod, CameraPod, EnumMaterialPod, EnumPrimitivePod, InstancePod, TexturePod,
+    VertexPod,
+};
+use serde::Deserialize;
+
+use crate::{gltf, obj, tonemap::srgb_eotf};
+use spirv_std::glam::{vec3, Mat4, Quat, Vec3};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename = "Scene")]
+pub struct SceneDesc {
+    /// Master seed of the render. `--seed` takes precedence
+    #[serde(default)]
+    pub seed: Option<u64>,
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub background: BackgroundDesc,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDesc>,
+    /// Textures referred to by `Lambertian` and `Metal` materials
+    #[serde(default)]
+    pub textures: BTreeMap<String, TextureDesc>,
+    /// Groups of objects placed in the scene by `ObjectDesc::Instance`
+    #[serde(default)]
+    pub prototypes: BTreeMap<String, Vec<ObjectDesc>>,
+    pub objects: Vec<ObjectDesc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CameraDesc {
+    pub look_from: [f32; 3],
+    pub look_at: [f32; 3],
+    #[serde(default = "default_vup")]
+    pub vup: [f32; 3],
+    /// Vertical field of view in degrees
+    pub vfov: f32,
+    #[serde(default)]
+    pub aperture: f32,
+    #[serde(default = "default_focus_dist")]
+    pub focus_dist: f32,
+    /// The shutter opens at `time0` and closes at `time1`. Rays are spread over the
+    /// interval, which blurs moving objects
+    #[serde(default)]
+    pub time0: f32,
+    #[serde(default = "default_time1")]
+    pub time1: f32,
+}
+
+fn default_vup() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_focus_dist() -> f32 {
+    1.0
+}
+
+fn default_time1() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum BackgroundDesc {
+    Sky,
+    Solid {
+        color: [f32; 3],
+    },
+    Gradient {
+        bottom: [f32; 3],
+        top: [f32; 3],
+    },
+    /// Equirectangular Radiance HDR image. Relative paths are resolved from the scene file
+    EnvironmentMap {
+        path: PathBuf,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+impl Default for BackgroundDesc {
+    fn default() -> Self {
+        BackgroundDesc::Sky
+    }
+}
+
+/// `texture` names an entry of `SceneDesc::textures`, which multiplies `albedo`
+#[derive(Debug, Clone, Deserialize)]
+pub enum MaterialDesc {
+    Lambertian {
+        #[serde(default = "default_albedo")]
+        albedo: [f32; 3],
+        #[serde(default)]
+        texture: Option<String>,
+    },
+    Metal {
+        #[serde(default = "default_albedo")]
+        albedo: [f32; 3],
+        fuzz: f32,
+        #[serde(default)]
+        texture: Option<String>,
+    },
+    Dielectric {
+        ir: f32,
+    },
+    DiffuseLight {
+        emit: [f32; 3],
+    },
+    /// Refers to an entry of `SceneDesc::materials`
+    Ref(String),
+}
+
+fn default_albedo() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+/// Looked up by the texture coordinates of the hit or by its position. Texture coordinates
+/// are the longitude and latitude on spheres and the position along the sides of quads and
+/// rectangles
+#[derive(Debug, Clone, Deserialize)]
+pub enum TextureDesc {
+    Solid {
+        color: [f32; 3],
+    },
+    /// Alternates between `even` and `odd` in cubes of side `scale`
+    Checker {
+        even: [f32; 3],
+        odd: [f32; 3],
+        #[serde(default = "default_checker_scale")]
+        scale: f32,
+    },
+    /// Veins of `color` along the z axis, `frequency` radians apart and distorted by Perlin
+    /// turbulence
+    Marble {
+        #[serde(default = "default_albedo")]
+        color: [f32; 3],
+        frequency: f32,
+    },
+    /// An sRGB image in a format of the `image` crate, such as PNG or JPEG. Relative paths
+    /// are resolved from the scene file
+    Image {
+        path: PathBuf,
+    },
+}
+
+fn default_checker_scale() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum ObjectDesc {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        material: MaterialDesc,
+    },
+    /// Moves linearly from `center0` at `time0` to `center1` at `time1`
+    MovingSphere {
+        center0: [f32; 3],
+        center1: [f32; 3],
+        #[serde(default)]
+        time0: f32,
+        #[serde(default = "default_time1")]
+        time1: f32,
+        radius: f32,
+        material: MaterialDesc,
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        material: MaterialDesc,
+    },
+    /// A parallelogram with a corner at `q` and sides `u` and `v`. Its front faces `u × v`
+    Quad {
+        q: [f32; 3],
+        u: [f32; 3],
+        v: [f32; 3],
+        material: MaterialDesc,
+    },
+    /// An axis-aligned rectangle. `min` and `max` must agree on exactly one axis, which the
+    /// rectangle is perpendicular to
+    Rect {
+        min: [f32; 3],
+        max: [f32; 3],
+        material: MaterialDesc,
+    },
+    /// An axis-aligned box made of six quads
+    Box {
+        min: [f32; 3],
+        max: [f32; 3],
+        material: MaterialDesc,
+    },
+    /// A volume of smoke or fog filling `boundary`. Light scatters in a random direction
+    /// after an exponentially distributed distance, on average `1 / density`
+    ConstantMedium {
+        boundary: BoundaryDesc,
+        density: f32,
+        albedo: [f32; 3],
+    },
+    /// An indexed triangle mesh. Without `normals` every face is flat shaded
+    Mesh {
+        positions: Vec<[f32; 3]>,
+        #[serde(default)]
+        normals: Vec<[f32; 3]>,
+        indices: Vec<[u32; 3]>,
+        material: MaterialDesc,
+    },
+    /// A Wavefront OBJ file. Relative paths are resolved from the scene file. Materials
+    /// come from the MTL files it references unless `material` overrides them
+    Obj {
+        path: PathBuf,
+        #[serde(default)]
+        transform: TransformDesc,
+        #[serde(default)]
+        material: Option<MaterialDesc>,
+    },
+    /// The meshes of the default scene of a glTF 2.0 file, `.gltf` or `.glb`. Relative
+    /// paths are resolved from the scene file. Its cameras are ignored
+    Gltf {
+        path: PathBuf,
+        #[serde(default)]
+        transform: TransformDesc,
+        #[serde(default)]
+        material: Option<MaterialDesc>,
+    },
+    /// An entry of `SceneDesc::prototypes` placed by `transform`. Every instance of a
+    /// prototype shares its primitives. `material` replaces all of its materials
+    Instance {
+        prototype: String,
+        #[serde(default)]
+        transform: TransformDesc,
+        #[serde(default)]
+        material: Option<MaterialDesc>,
+    },
+}
+
+/// The convex shape of a `ObjectDesc::ConstantMedium`
+#[derive(Debug, Clone, Deserialize)]
+pub enum BoundaryDesc {
+    Sphere { center: [f32; 3], radius: f32 },
+    Box { min: [f32; 3], max: [f32; 3] },
+}
+
+/// Scales, then rotates, then translates an object
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransformDesc {
+    #[serde(default)]
+    pub translate: [f32; 3],
+    /// Rotation in degrees about the x, y and z axes, applied in that order
+    #[serde(default)]
+    pub rotate: [f32; 3],
+    #[serde(default = "default_scale")]
+    pub scale: [f32; 3],
+}
+
+fn default_scale() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        Self {
+            translate: [0.0, 0.0, 0.0],
+            rotate: [0.0, 0.0, 0.0],
+            scale: default_scale(),
+        }
+    }
+}
+
+impl TransformDesc {
+    pub fn to_mat4(&self) -> Mat4 {
+        let [x, y, z] = self.rotate;
+        let rotation = Quat::from_rotation_z(z.to_radians())
+            * Quat::from_rotation_y(y.to_radians())
+            * Quat::from_rotation_x(x.to_radians());
+        Mat4::from_scale_rotation_translation(
+            to_vec3(self.scale),
+            rotation,
+            to_vec3(self.translate),
+        )
+    }
+}
+
+pub struct Scene {
+    pub camera: CameraDesc,
+    /// Primitives in world space, followed by those of `objects`
+    pub world: Vec<EnumPrimitivePod>,
+    /// Ranges of `world` placed by `instances`
+    pub objects: Vec<Range<usize>>,
+    pub instances: Vec<InstancePod>,
+    /// Vertices indexed by the triangles of `world`
+    pub vertices: Vec<VertexPod>,
+    pub background: BackgroundPod,
+    pub textures: Vec<TexturePod>,
+    /// Texels of the environment map, top row first, followed by those of `textures`
+    pub texels: Vec<[f32; 4]>,
+    pub seed: Option<u64>,
+}
+
+fn to_vec3(v: [f32; 3]) -> Vec3 {
+    vec3(v[0], v[1], v[2])
+}
+
+/// Moves the vertices of a mesh by `transform`, e.g. that of the node or object placing it
+pub fn transform_mesh(transform: Mat4, positions: &mut [[f32; 3]], normals: &mut [[f32; 3]]) {
+    for p in positions {
+        *p = transform.transform_point3(to_vec3(*p)).into();
+    }
+    // Normals are transformed by the inverse transpose to stay perpendicular under
+    // non-uniform scaling
+    let normal_transform = transform.inverse().transpose();
+    for n in normals {
+        *n = normal_transform.transform_vector3(to_vec3(*n)).into();
+    }
+}
+
+/// An empty directory for the files of a test, unique to `name` and the process
+#[cfg(test)]
+pub fn fixture_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("rukako-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Appends an indexed mesh to the primitive and vertex buffers of a scene
+pub fn add_mesh(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    indices: &[[u32; 3]],
+    material: EnumMaterialPod,
+    world: &mut Vec<EnumPrimitivePod>,
+    vertices: &mut Vec<VertexPod>,
+) -> anyhow::Result<()> {
+    if !normals.is_empty() && normals.len() != positions.len() {
+        bail!(
+            "mesh has {} positions but {} normals",
+            positions.len(),
+            normals.len()
+        );
+    }
+    if let Some(index) = indices
+        .iter()
+        .flatten()
+        .find(|&&i| i as usize >= positions.len())
+    {
+        bail!(
+            "mesh index {} is out of range for {} positions",
+            index,
+            positions.len()
+        );
+    }
+
+    let base = vertices.len() as u32;
+    vertices.extend(
+        positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| match normals.get(i) {
+                Some(n) => VertexPod::new(to_vec3(*p), to_vec3(*n).normalize()),
+                None => VertexPod::without_normal(to_vec3(*p)),
+            }),
+    );
+    world.extend(
+        indices.iter().map(|[a, b, c]| {
+            EnumPrimitivePod::new_triangle([base + a, base + b, base + c], material)
+        }),
+    );
+
+    Ok(())
+}
+
+impl CameraDesc {
+    pub fn to_pod(&self, aspect_ratio: f32) -> CameraPod {
+        CameraPod::new(
+            to_vec3(self.look_from),
+            to_vec3(self.look_at),
+            to_vec3(self.vup),
+            self.vfov.to_radians(),
+            aspect_ratio,
+            self.aperture,
+            self.focus_dist,
+            self.time0,
+            self.time1,
+        )
+    }
+}
+
+impl BackgroundDesc {
+    pub fn load(&self, base_dir: &Path) -> anyhow::Result<(BackgroundPod, Vec<[f32; 4]>)> {
+        Ok(match self {
+            BackgroundDesc::Sky => (BackgroundPod::new_sky(), Vec::new()),
+            BackgroundDesc::Solid { color } => {
+                (BackgroundPod::new_solid(to_vec3(*color)), Vec::new())
+            }
+            BackgroundDesc::Gradient { bottom, top } => (
+                BackgroundPod::new_gradient(to_vec3(*bottom), to_vec3(*top)),
+                Vec::new(),
+            ),
+            BackgroundDesc::EnvironmentMap { path, intensity } => {
+                let path = base_dir.join(path);
+                let (width, height, texels) = load_hdr(&path)
+                    .with_context(|| format!("loading environment map {}", path.display()))?;
+                (
+                    BackgroundPod::new_environment_map(width, height, *intensity),
+                    texels,
+                )
+            }
+        })
+    }
+}
+
+impl TextureDesc {
+    /// Appends the texels the texture reads to `texels`. `perlin` is the first texel of the
+    /// Perlin tables shared by every marble texture, once a marble texture has added them
+    fn load(
+        &self,
+        base_dir: &Path,
+        texels: &mut Vec<[f32; 4]>,
+        perlin: &mut Option<u32>,
+    ) -> anyhow::Result<TexturePod> {
+        Ok(match self {
+            TextureDesc::Solid { color } => TexturePod::new_solid(to_vec3(*color)),
+            TextureDesc::Checker { even, odd, scale } => {
+                if *scale <= 0.0 {
+                    bail!("the scale of a checker must be positive");
+                }
+                TexturePod::new_checker(to_vec3(*even), to_vec3(*odd), *scale)
+            }
+            TextureDesc::Marble { color, frequency } => {
+                let first = *perlin.get_or_insert_with(|| {
+                    let first = texels.len() as u32;
+                    // A fixed seed keeps the veins in place whatever the seed of the render
+                    texels.extend(perlin::texels(&mut StdRng::seed_from_u64(0)));
+                    first
+                });
+                TexturePod::new_marble(to_vec3(*color), *frequency, first)
+            }
+            TextureDesc::Image { path } => {
+                let path = base_dir.join(path);
+                let image = image::open(&path)
+                    .with_context(|| format!("loading image {}", path.display()))?
+                    .to_rgb8();
+                let first = texels.len() as u32;
+                texels.extend(image.pixels().map(|p| {
+                    let [r, g, b] = p.0;
+                    let f = |c: u8| srgb_eotf(c as f32 / 255.0);
+                    [f(r), f(g), f(b), 1.0]
+                }));
+                TexturePod::new_image(image.width(), image.height(), first)
+            }
+        })
+    }
+}
+
+fn load_hdr(path: &Path) -> anyhow::Result<(u32, u32, Vec<[f32; 4]>)> {
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+    let metadata = decoder.metadata();
+    let texels = decoder
+        .read_image_hdr()?
+        .into_iter()
+        .map(|p| [p[0], p[1], p[2], 1.0])
+        .collect();
+    Ok((metadata.width, metadata.height, texels))
+}
+
+impl SceneDesc {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        ron::de::from_str(s).map_err(|e| {
+            anyhow!(
+                "line {}, column {}: {}",
+                e.position.line,
+                e.position.col,
+                e.code
+            )
+        })
+    }
+
+    fn resolve_material(&self, material: &MaterialDesc) -> anyhow::Result<EnumMaterialPod> {
+        Ok(match material {
+            MaterialDesc::Lambertian { albedo, texture } => {
+                self.apply_texture(EnumMaterialPod::new_lambertian(to_vec3(*albedo)), texture)?
+            }
+            MaterialDesc::Metal {
+                albedo,
+                fuzz,
+                texture,
+            } => {
+                self.apply_texture(EnumMaterialPod::new_metal(to_vec3(*albedo), *fuzz), texture)?
+            }
+            MaterialDesc::Dielectric { ir } => EnumMaterialPod::new_dielectric(*ir),
+            MaterialDesc::DiffuseLight { emit } => {
+                EnumMaterialPod::new_diffuse_light(to_vec3(*emit))
+            }
+            MaterialDesc::Ref(name) => match self.materials.get(name) {
+                Some(MaterialDesc::Ref(_)) => {
+                    bail!("material {:?} refers to another material", name)
+                }
+                Some(material) => self.resolve_material(material)?,
+                None => bail!("unknown material {:?}", name),
+            },
+        })
+    }
+
+    /// Textures are numbered in the order of `SceneDesc::textures`, as `build` loads them
+    fn apply_texture(
+        &self,
+        material: EnumMaterialPod,
+        texture: &Option<String>,
+    ) -> anyhow::Result<EnumMaterialPod> {
+        Ok(match texture {
+            Some(name) => match self.textures.keys().position(|key| key == name) {
+                Some(index) => material.with_texture(index as u32),
+                None => bail!("unknown texture {:?}", name),
+            },
+            None => material,
+        })
+    }
+
+    /// Appends the primitives of a single object
+    fn add_object(
+        &self,
+        object: &ObjectDesc,
+        base_dir: &Path,
+        world: &mut Vec<EnumPrimitivePod>,
+        vertices: &mut Vec<VertexPod>,
+    ) -> anyhow::Result<()> {
+        match object {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                let material = self.resolve_material(material)?;
+                world.push(EnumPrimitivePod::new_sphere(
+                    to_vec3(*center),
+                    *radius,
+                    material,
+                ));
+            }
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => {
+                if time1 <= time0 {
+                    bail!("time1 must be greater than time0");
+                }
+                let material = self.resolve_material(material)?;
+                world.push(EnumPrimitivePod::new_moving_sphere(
+                    to_vec3(*center0),
+                    to_vec3(*center1),
+                    *time0,
+                    *time1,
+                    *radius,
+                    material,
+                ));
+            }
+            ObjectDesc::Triangle {
+                vertices: corners,
+                material,
+            } => {
+                let material = self.resolve_material(material)?;
+                let base = vertices.len() as u32;
+                vertices.extend(
+                    corners
+                        .iter()
+                        .map(|p| VertexPod::without_normal(to_vec3(*p))),
+                );
+                world.push(EnumPrimitivePod::new_triangle(
+                    [base, base + 1, base + 2],
+                    material,
+                ));
+            }
+            ObjectDesc::Quad { q, u, v, material } => {
+                if to_vec3(*u).cross(to_vec3(*v)).length_squared() == 0.0 {
+                    bail!("the sides of a quad must not be parallel");
+                }
+                let material = self.resolve_material(material)?;
+                world.push(EnumPrimitivePod::new_quad(
+                    to_vec3(*q),
+                    to_vec3(*u),
+                    to_vec3(*v),
+                    material,
+                ));
+            }
+            ObjectDesc::Rect { min, max, material } => {
+                let flat = (0..3).filter(|&k| min[k] == max[k]).collect::<Vec<_>>();
+                let axis = match flat[..] {
+                    [axis] => axis as u32,
+                    _ => bail!("a rectangle must be flat along exactly one axis"),
+                };
+                let material = self.resolve_material(material)?;
+                world.push(EnumPrimitivePod::new_rect(
+                    axis,
+                    to_vec3(*min).min(to_vec3(*max)),
+                    to_vec3(*min).max(to_vec3(*max)),
+                    material,
+                ));
+            }
+            ObjectDesc::Box { min, max, material } => {
+                if (0..3).any(|k| min[k] == max[k]) {
+                    bail!("a box must not be flat along any axis");
+                }
+                let material = self.resolve_material(material)?;
+                world.extend_from_slice(&EnumPrimitivePod::new_box(
+                    to_vec3(*min),
+                    to_vec3(*max),
+                    material,
+                ));
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+            } => {
+                if *density <= 0.0 {
+                    bail!("the density of a medium must be positive");
+                }
+                let material = EnumMaterialPod::new_isotropic(to_vec3(*albedo));
+                world.push(match boundary {
+                    BoundaryDesc::Sphere { center, radius } => {
+                        EnumPrimitivePod::new_constant_medium_sphere(
+                            to_vec3(*center),
+                            *radius,
+                            *density,
+                            material,
+                        )
+                    }
+                    BoundaryDesc::Box { min, max } => EnumPrimitivePod::new_constant_medium_box(
+                        to_vec3(*min).min(to_vec3(*max)),
+                        to_vec3(*min).max(to_vec3(*max)),
+                        *density,
+                        material,
+                    ),
+                });
+            }
+            ObjectDesc::Mesh {
+                positions,
+                normals,
+                indices,
+                material,
+            } => {
+                let material = self.resolve_material(material)?;
+                add_mesh(positions, normals, indices, material, world, vertices)?;
+            }
+            ObjectDesc::Obj {
+                path,
+                transform,
+                material,
+            } => {
+                let material = material
+                    .as_ref()
+                    .map(|material| self.resolve_material(material))
+                    .transpose()?;
+                let path = base_dir.join(path);
+                obj::load(&path, transform.to_mat4(), material, world, vertices)
+                    .with_context(|| format!("loading {}", path.display()))?;
+            }
+            ObjectDesc::Gltf {
+                path,
+                transform,
+                material,
+            } => {
+                let material = material
+                    .as_ref()
+                    .map(|material| self.resolve_material(material))
+                    .transpose()?;
+                let path = base_dir.join(path);
+                gltf::load(&path, transform.to_mat4(), material, world, vertices)
+                    .with_context(|| format!("loading {}", path.display()))?;
+            }
+            ObjectDesc::Instance { .. } => bail!("prototypes cannot contain instances"),
+        }
+
+        Ok(())
+    }
+
+    pub fn build(&self, base_dir: &Path) -> anyhow::Result<Scene> {
+        let mut world = Vec::new();
+        let mut vertices = Vec::new();
+        let mut instances = Vec::new();
+        // Prototypes in the order they are first instanced
+        let mut prototypes: Vec<&str> = Vec::new();
+
+        for (i, object) in self.objects.iter().enumerate() {
+            match object {
+                ObjectDesc::Instance {
+                    prototype,
+                    transform,
+                    material,
+                } => {
+                    if !self.prototypes.contains_key(prototype) {
+                        bail!("object #{}: unknown prototype {:?}", i, prototype);
+                    }
+                    let transform = transform.to_mat4();
+                    if transform.determinant() == 0.0 {
+                        bail!(
+                            "object #{}: the transform of an instance must be invertible",
+                            i
+                        );
+                    }
+                    let material = material
+                        .as_ref()
+                        .map(|material| self.resolve_material(material))
+                        .transpose()
+                        .with_context(|| format!("object #{}", i))?;
+                    let index = match prototypes.iter().position(|&name| name == prototype) {
+                        Some(index) => index,
+                        None => {
+                            prototypes.push(prototype);
+                            prototypes.len() - 1
+                        }
+                    };
+                    instances.push(InstancePod::new(transform, index as u32, material));
+                }
+                _ => self
+                    .add_object(object, base_dir, &mut world, &mut vertices)
+                    .with_context(|| format!("object #{}", i))?,
+            }
+        }
+
+        let mut objects = Vec::with_capacity(prototypes.len());
+        for name in prototypes {
+            let start = world.len();
+            for (i, object) in self.prototypes[name].iter().enumerate() {
+                self.add_object(object, base_dir, &mut world, &mut vertices)
+                    .with_context(|| format!("prototype {:?}, object #{}", name, i))?;
+            }
+            if world.len() == start {
+                bail!("prototype {:?} has no objects", name);
+            }
+            objects.push(start..world.len());
+        }
+
+        if world.is_empty() {
+            bail!("scene has no objects");
+        }
+        if self.camera.time1 < self.camera.time0 {
+            bail!("camera: time1 must not be less than time0");
+        }
+
+        let (background, mut texels) = self.background.load(base_dir)?;
+        let mut perlin = None;
+        let textures = self
+            .textures
+            .iter()
+            .map(|(name, texture)| {
+                texture
+                    .load(base_dir, &mut texels, &mut perlin)
+                    .with_context(|| format!("texture {:?}", name))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Scene {
+            camera: self.camera.clone(),
+            world,
+            objects,
+            instances,
+            vertices,
+            background,
+            textures,
+            texels,
+            seed: self.seed,
+        })
+    }
+}
+
+/// Loads a RON scene description, or renders a `.gltf` or `.glb` file on its own
+pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Scene> {
+    let path = path.as_ref();
+    if let Some("gltf") | Some("glb") = path.extension().and_then(|ext| ext.to_str()) {
+        return gltf::load_scene(path).with_context(|| format!("loading {}", path.display()));
+    }
+    let s = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    SceneDesc::parse(&s)
+        .and_then(|desc| desc.build(path.parent().unwrap_or_else(|| Path::new(""))))
+        .with_context(|| format!("loading {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scene with the given materials, prototypes and objects
+    fn scene(extra: &str, objects: &str) -> String {
+        format!(
+            "Scene(camera: (look_from: (0.0, 0.0, 1.0), look_at: (0.0, 0.0, 0.0), vfov: 40.0), \
+             {} objects: [{}])",
+            extra, objects
+        )
+    }
+
+    fn build_error(extra: &str, objects: &str) -> String {
+        let desc = SceneDesc::parse(&scene(extra, objects)).unwrap();
+        match desc.build(Path::new("")) {
+            Ok(_) => panic!("{} built", objects),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    fn assert_error(extra: &str, objects: &str, expected: &str) {
+        let error = build_error(extra, objects);
+        assert!(error.contains(expected), "{:?} lacks {:?}", error, expected);
+    }
+
+    const GRAY: &str = "Lambertian(albedo: (0.5, 0.5, 0.5))";
+
+    #[test]
+    fn weekend_follows_random_scene() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/weekend.ron");
+        let desc = SceneDesc::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(desc.materials.len(), 2);
+        assert_eq!(desc.objects.len(), 485);
+
+        // Small spheres are 0.3 when diffuse and 0.2 otherwise, as in `random_scene`
+        for object in &desc.objects[1..desc.objects.len() - 3] {
+            match object {
+                ObjectDesc::Sphere {
+                    center,
+                    radius,
+                    material,
+                } => {
+                    assert_eq!(center[1], 0.2);
+                    let expected = match material {
+                        MaterialDesc::Lambertian { .. } => 0.3,
+                        _ => 0.2,
+                    };
+                    assert_eq!(*radius, expected, "sphere at {:?}", center);
+                }
+                _ => panic!("{:?} is not a sphere", object),
+            }
+        }
+        for object in &desc.objects[desc.objects.len() - 3..] {
+            match object {
+                ObjectDesc::Sphere { radius, .. } => assert_eq!(*radius, 1.0),
+                _ => panic!("{:?} is not a sphere", object),
+            }
+        }
+
+        let scene = load(&path).unwrap();
+        assert_eq!(scene.world.len(), 485);
+        assert!(scene.instances.is_empty());
+        assert!(scene.vertices.is_empty());
+    }
+
+    #[test]
+    fn parse_errors_have_a_position() {
+        let error = SceneDesc::parse("Scene(\n    camera: (\n        look_from: oops,\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("line 3, column 20:"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_material() {
+        assert_error(
+            "",
+            "Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: Ref(\"nope\"))",
+            "object #0: unknown material \"nope\"",
+        );
+    }
+
+    #[test]
+    fn singular_instance() {
+        assert_error(
+            &format!(
+                "prototypes: {{\"ball\": [Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, \
+                 material: {})]}},",
+                GRAY
+            ),
+            "Instance(prototype: \"ball\", transform: (scale: (1.0, 0.0, 1.0)))",
+            "object #0: the transform of an instance must be invertible",
+        );
+    }
+
+    #[test]
+    fn backwards_moving_sphere() {
+        assert_error(
+            "",
+            &format!(
+                "MovingSphere(center0: (0.0, 0.0, 0.0), center1: (1.0, 0.0, 0.0), time0: 1.0, \
+                 time1: 1.0, radius: 1.0, material: {})",
+                GRAY
+            ),
+            "time1 must be greater than time0",
+        );
+    }
+
+    #[test]
+    fn degenerate_quad() {
+        assert_error(
+            "",
+            &format!(
+                "Quad(q: (0.0, 0.0, 0.0), u: (1.0, 0.0, 0.0), v: (2.0, 0.0, 0.0), material: {})",
+                GRAY
+            ),
+            "the sides of a quad must not be parallel",
+        );
+    }
+
+    #[test]
+    fn degenerate_rect() {
+        for (min, max) in &[
+            ("(0.0, 0.0, 0.0)", "(1.0, 1.0, 1.0)"),
+            ("(0.0, 0.0, 0.0)", "(1.0, 0.0, 0.0)"),
+        ] {
+            assert_error(
+                "",
+                &format!("Rect(min: {}, max: {}, material: {})", min, max, GRAY),
+                "a rectangle must be flat along exactly one axis",
+            );
+        }
+    }
+
+    #[test]
+    fn flat_box() {
+        assert_error(
+            "",
+            &format!(
+                "Box(min: (0.0, 0.0, 0.0), max: (1.0, 0.0, 1.0), material: {})",
+                GRAY
+            ),
+            "a box must not be flat along any axis",
+        );
+    }
+
+    #[test]
+    fn empty_medium() {
+        assert_error(
+            "",
+            "ConstantMedium(boundary: Sphere(center: (0.0, 0.0, 0.0), radius: 1.0), \
+             density: 0.0, albedo: (1.0, 1.0, 1.0))",
+            "the density of a medium must be positive",
+        );
+    }
+
+    #[test]
+    fn checker_without_scale() {
+        assert_error(
+            "textures: {\"tiles\": Checker(even: (0.0, 0.0, 0.0), odd: (1.0, 1.0, 1.0), \
+             scale: 0.0)},",
+            "Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, \
+             material: Lambertian(texture: Some(\"tiles\")))",
+            "texture \"tiles\": the scale of a checker must be positive",
+        );
+    }
+
+    #[test]
+    fn mesh_indices_are_checked() {
+        let material = EnumMaterialPod::new_lambertian(vec3(0.5, 0.5, 0.5));
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let (mut world, mut vertices) = (Vec::new(), Vec::new());
+
+        let error = add_mesh(
+            &positions,
+            &[],
+            &[[0, 1, 3]],
+            material,
+            &mut world,
+            &mut vertices,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "mesh index 3 is out of range for 3 positions"
+        );
+
+        let error = add_mesh(
+            &positions,
+            &[[0.0, 0.0, 1.0]],
+            &[[0, 1, 2]],
+            material,
+            &mut world,
+            &mut vertices,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "mesh has 3 positions but 1 normals");
+
+        // Nothing is added on failure
+        assert!(world.is_empty());
+        assert!(vertices.is_empty());
+
+        add_mesh(
+            &positions,
+            &[],
+            &[[0, 1, 2]],
+            material,
+            &mut world,
+            &mut vertices,
+        )
+        .unwrap();
+        assert_eq!(world.len(), 1);
+        assert_eq!(vertices.len(), 3);
+    }
+}
@@ -0,0 +1,199 @@
+use structopt::clap::arg_enum;
+
+use crate::output::LinearImage;
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Operator {
+        Clamp,
+        Reinhard,
+        Aces,
+        Agx,
+    }
+}
+
+/// Turns linear radiance into 8-bit sRGB
+#[derive(Debug, Clone)]
+pub struct Tonemapper {
+    pub operator: Operator,
+    /// Exposure compensation in stops
+    pub exposure: f32,
+    /// Add noise of one quantisation step before rounding to hide banding
+    pub dither: bool,
+}
+
+impl Default for Tonemapper {
+    fn default() -> Self {
+        Self {
+            operator: Operator::Clamp,
+            exposure: 0.0,
+            dither: false,
+        }
+    }
+}
+
+impl Tonemapper {
+    /// Returns display-referred linear RGB in [0, 1]
+    pub fn map(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let scale = self.exposure.exp2();
+        let [r, g, b] = rgb;
+        let rgb = [r * scale, g * scale, b * scale];
+
+        match self.operator {
+            Operator::Clamp => rgb.map_each(|c| c.clamp(0.0, 1.0)),
+            Operator::Reinhard => rgb.map_each(|c| {
+                let c = c.max(0.0);
+                c / (1.0 + c)
+            }),
+            Operator::Aces => rgb.map_each(aces),
+            Operator::Agx => agx(rgb),
+        }
+    }
+
+    pub fn to_rgba8(&self, image: &LinearImage) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(4 * image.pixels.len());
+        for (i, p) in image.pixels.iter().enumerate() {
+            let mapped = self.map(*p);
+            for (c, v) in mapped.iter().enumerate() {
+                let v = 255.0 * srgb_oetf(*v);
+                let noise = if self.dither {
+                    dither_noise(i as u32 * 4 + c as u32)
+                } else {
+                    0.0
+                };
+                rgba.push((v + noise).round().clamp(0.0, 255.0) as u8);
+            }
+            rgba.push(255);
+        }
+        rgba
+    }
+}
+
+trait MapEach {
+    fn map_each(self, f: impl Fn(f32) -> f32) -> Self;
+}
+
+impl MapEach for [f32; 3] {
+    fn map_each(self, f: impl Fn(f32) -> f32) -> Self {
+        [f(self[0]), f(self[1]), f(self[2])]
+    }
+}
+
+/// The piecewise sRGB transfer function of IEC 61966-2-1
+pub fn srgb_oetf(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `srgb_oetf`, for decoding images into linear values
+pub fn srgb_eotf(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(c: f32) -> f32 {
+    let c = c.max(0.0);
+    ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+/// AgX with the default look, after Benjamin Wrensch's polynomial approximation
+fn agx(rgb: [f32; 3]) -> [f32; 3] {
+    const MIN_EV: f32 = -12.473_931;
+    const MAX_EV: f32 = 4.026_069;
+
+    let [r, g, b] = rgb;
+    // Inset into the AgX working space
+    let inset = [
+        0.842_479_06 * r + 0.078_433_6 * g + 0.079_223_745 * b,
+        0.042_328_24 * r + 0.878_468_6 * g + 0.079_166_13 * b,
+        0.042_375_655 * r + 0.078_433_6 * g + 0.879_143 * b,
+    ];
+
+    let [r, g, b] = inset.map_each(|c| {
+        let c = (c.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let c2 = c * c;
+        let c4 = c2 * c2;
+        15.5 * c4 * c2 - 40.14 * c4 * c + 31.96 * c4 - 6.868 * c2 * c + 0.4298 * c2 + 0.1191 * c
+            - 0.002_32
+    });
+
+    // Outset back. The curve produces display-encoded values, so undo its 2.2 gamma
+    [
+        1.196_879 * r - 0.098_020_88 * g - 0.099_029_74 * b,
+        -0.052_896_85 * r + 1.151_903_1 * g - 0.098_961_18 * b,
+        -0.052_971_635 * r - 0.098_043_45 * g + 1.151_073_7 * b,
+    ]
+    .map_each(|c| c.clamp(0.0, 1.0).powf(2.2))
+}
+
+/// Deterministic noise in [-0.5, 0.5) so that dithered renders stay reproducible
+fn dither_noise(i: u32) -> f32 {
+    // PCG hash
+    let state = i.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    let word = (word >> 22) ^ word;
+    (word >> 8) as f32 / (1 << 24) as f32 - 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_oetf_is_continuous() {
+        let below = srgb_oetf(0.003_130_8);
+        let above = srgb_oetf(0.003_130_9);
+        assert!((below - above).abs() < 1e-4);
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn srgb_eotf_inverts_oetf() {
+        for i in 0..=100 {
+            let c = i as f32 / 100.0;
+            assert!((srgb_eotf(srgb_oetf(c)) - c).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn operators_are_monotonic_and_bounded() {
+        for &operator in &[
+            Operator::Clamp,
+            Operator::Reinhard,
+            Operator::Aces,
+            Operator::Agx,
+        ] {
+            let tonemapper = Tonemapper {
+                operator,
+                ..Default::default()
+            };
+            let mut last = -1.0;
+            for i in 0..1000 {
+                let x = i as f32 * 0.02;
+                let [y, _, _] = tonemapper.map([x, x, x]);
+                assert!((0.0..=1.0).contains(&y), "{:?}({}) = {}", operator, x, y);
+                assert!(y >= last - 1e-4, "{:?} decreases at {}", operator, x);
+                last = y;
+            }
+        }
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let tonemapper = Tonemapper {
+            exposure: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(tonemapper.map([0.25, 0.25, 0.25]), [0.5, 0.5, 0.5]);
+    }
+}